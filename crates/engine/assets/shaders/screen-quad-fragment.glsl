@@ -3,9 +3,96 @@
 layout(set = 0, binding = 0) uniform texture2D u_texture;
 layout(set = 0, binding = 1) uniform sampler u_sampler;
 
+// devices without push constants get the config in a uniform buffer
+#ifdef PUSH_CONSTANTS
+#define CONFIG_LAYOUT layout(push_constant)
+#else
+#define CONFIG_LAYOUT layout(std140, set = 0, binding = 2)
+#endif
+
+CONFIG_LAYOUT uniform Config {
+    uvec2 viewport_size;
+    // in stops
+    float exposure;
+    uint tonemapping;
+    uint flags;
+} config;
+
 in vec2 texture_coords;
 out vec4 result_color;
 
+const uint TONEMAPPING_REINHARD = 0;
+const uint TONEMAPPING_ACES = 1;
+const uint TONEMAPPING_AGX = 2;
+// targets without an `Srgb` format need the transfer function applied by hand
+const uint FLAG_ENCODE_SRGB = 1;
+// off for textures holding display colors already
+const uint FLAG_TONEMAP = 2;
+
+// see https://iolite-engine.com/blog_posts/minimal_agx_implementation
+vec3 agx(vec3 color) {
+    const mat3 inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104
+    );
+    const mat3 outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+    );
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    vec3 x = log2(max(inset * color, vec3(1.0e-10)));
+    x = (clamp(x, min_ev, max_ev) - min_ev) / (max_ev - min_ev);
+
+    // polynomial fit of the AgX base contrast curve
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    vec3 curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+
+    // the curve outputs display values with a 2.2 gamma
+    return pow(clamp(outset * curve, 0.0, 1.0), vec3(2.2));
+}
+
+// maps unbounded linear radiance into [0, 1] keeping it linear
+vec3 tonemap(vec3 color) {
+    color = max(color, vec3(0.0));
+
+    switch (config.tonemapping) {
+    case TONEMAPPING_REINHARD:
+        return color / (1.0 + color);
+    case TONEMAPPING_AGX:
+        return agx(color);
+    default:
+        // Narkowicz fit of the ACES filmic curve
+        return clamp(
+            (color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0
+        );
+    }
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(
+        12.92 * color,
+        1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055,
+        greaterThan(color, vec3(0.0031308))
+    );
+}
+
 void main() {
-    result_color = texture(sampler2D(u_texture, u_sampler), texture_coords);
+    vec4 color = texture(sampler2D(u_texture, u_sampler), texture_coords);
+    vec3 mapped = color.rgb;
+
+    if (0 != (config.flags & FLAG_TONEMAP)) {
+        mapped = tonemap(exp2(config.exposure) * mapped);
+    }
+
+    if (0 != (config.flags & FLAG_ENCODE_SRGB)) {
+        mapped = linear_to_srgb(mapped);
+    }
+
+    result_color = vec4(mapped, color.a);
 }
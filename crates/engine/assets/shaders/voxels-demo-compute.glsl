@@ -2,36 +2,242 @@
 
 layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
 
-layout(std430, binding = 0) readonly buffer Voxels {
-    uint colors[];
+struct Node {
+    uint kind;
+    uint data;
 };
 
-layout(rgba8, binding = 1) uniform image2D screen;
+layout(std430, binding = 0) readonly buffer Octree {
+    Node nodes[];
+};
+
+// linear HDR radiance
+layout(rgba16f, binding = 1) writeonly uniform image2D screen;
+
+// sum of all path traced samples since the last reset
+layout(rgba32f, binding = 5) uniform image2D accumulation;
+
+layout(std430, binding = 2) readonly buffer ChunkTable {
+    uint chunk_roots[];
+};
+
+layout(std140, binding = 3) uniform Camera {
+    vec3 position;
+    float near;
+    vec3 forward;
+    float far;
+    vec3 right;
+    float tan_half_vfov;
+    vec3 up;
+    float half_height;
+    uint projection;
+} camera;
+
+struct Light {
+    // position of point lights or normalized direction of directional lights
+    vec3 position;
+    uint kind;
+    vec3 color;
+};
+
+layout(std430, binding = 4) readonly buffer Lights {
+    uint light_count;
+    Light lights[];
+};
+
+struct Material {
+    // multiplies the voxel color
+    vec3 albedo;
+    float roughness;
+    vec3 emission;
+    float metalness;
+    // index of refraction
+    float ior;
+    // multiplies the voxel color alpha
+    float opacity;
+};
+
+// always has 256 entries, so any voxel material index is valid
+layout(std430, binding = 6) readonly buffer Materials {
+    Material materials[];
+};
 
-layout(push_constant) uniform struct Config {
+layout(std140, binding = 7) uniform Environment {
+    // color of uniform environments
+    vec3 zenith;
+    uint kind;
+    vec3 horizon;
+    // multiplies the environment map
+    float intensity;
+    vec3 ground;
+    // cosine-weighted average radiance around +X, -X, +Y, -Y, +Z and -Z
+    vec4 ambient[6];
+} environment;
+
+// equirectangular, wraps around horizontally
+layout(binding = 8) uniform texture2D environment_map;
+layout(binding = 9) uniform sampler environment_sampler;
+
+// devices without push constants get the config in a uniform buffer
+#ifdef PUSH_CONSTANTS
+#define CONFIG_LAYOUT layout(push_constant)
+#else
+#define CONFIG_LAYOUT layout(std140, binding = 10)
+#endif
+
+CONFIG_LAYOUT uniform Config {
     uvec2 viewport_size;
     uvec2 render_texture_size;
+    ivec3 chunk_lo;
     float time;
+    uvec3 chunk_count;
+    uint render_mode;
+    uint sample_index;
+    uint max_bounces;
+    uint flags;
+    uint ambient_occlusion;
+    uint debug_view;
 } config;
 
 const float PI = 3.1415926535;
+const float INFINITY = 1.0e30;
+const uint NODE_LEAF = 0;
+const uint NODE_BRANCH = 1;
+// leaf nodes keep the material index in the bits of `kind` starting from this one
+const uint NODE_MATERIAL_SHIFT = 8;
+const uint MAX_TRAVERSAL_STEPS = 1024;
+const uint NO_CHUNK = 0xFFFFFFFF;
+const uint PROJECTION_PERSPECTIVE = 0;
+const uint PROJECTION_ORTHOGRAPHIC = 1;
+const int CHUNK_DEPTH = 4;
+const int CHUNK_SIZE = 1 << CHUNK_DEPTH;
+const uint RENDER_MODE_RAYTRACE = 0;
+const uint RENDER_MODE_PATH_TRACE = 1;
+const uint LIGHT_POINT = 0;
+const uint LIGHT_DIRECTIONAL = 1;
+const uint FLAG_SHADOWS = 1;
+const uint FLAG_REFRACTION = 2;
+const uint AMBIENT_OCCLUSION_OFF = 0;
+const uint AMBIENT_OCCLUSION_NEIGHBORS = 1;
+const uint AMBIENT_OCCLUSION_TRACED = 2;
+const uint AMBIENT_OCCLUSION_SAMPLES = 8;
+const float AMBIENT_OCCLUSION_DISTANCE = 4.0;
+// secondary rays start this far off the surface to not hit the voxel they leave
+const float SURFACE_OFFSET = 1.0e-3;
+// surfaces a camera or shadow ray passes through before it gives up
+const uint MAX_TRANSPARENT_LAYERS = 8;
+const float OPAQUE_ALPHA = 0.99;
+const uint ENVIRONMENT_UNIFORM = 0;
+const uint ENVIRONMENT_GRADIENT = 1;
+const uint ENVIRONMENT_MAP = 2;
+const uint DEBUG_VIEW_OFF = 0;
+const uint DEBUG_VIEW_NORMALS = 1;
+const uint DEBUG_VIEW_DEPTH = 2;
+const uint DEBUG_VIEW_STEPS = 3;
+const uint DEBUG_VIEW_VOXEL_COORDS = 4;
+const uint DEBUG_VIEW_BOUNDARIES = 5;
+// step count shown as the hottest color of the heatmap
+const float DEBUG_MAX_STEPS = 64.0;
+// in voxels
+const float DEBUG_LINE_WIDTH = 0.05;
+
+uint rng_state;
+
+// PCG hash, see https://jcgt.org/published/0009/03/02/
+uint pcg_hash(uint value) {
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
 
-vec4 get_voxel_color(ivec3 pos) {
-    if (pos.x < -8 || 8 <= pos.x || pos.y < -8 || 8 <= pos.y || pos.z < -8 || 8 <= pos.z) {
-        return vec4(0.0);
-    }
-
-    pos += ivec3(8, 8, 8);
+// uniform in [0, 1)
+float random_float() {
+    rng_state = pcg_hash(rng_state);
+    return float(rng_state >> 8) / 16777216.0;
+}
 
-    uint index = 16 * (16 * pos.z + pos.y) + pos.x;
-    uint color_pack = colors[index];
+vec3 srgb_to_linear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), greaterThan(color, vec3(0.04045)));
+}
 
-    return vec4(
+// voxel colors are sRGB encoded with linear alpha
+vec4 unpack_color(uint color_pack) {
+    vec4 color = vec4(
         float((color_pack >> 0) & 255) / 255.0,
         float((color_pack >> 8) & 255) / 255.0,
         float((color_pack >> 16) & 255) / 255.0,
         float((color_pack >> 24) & 255) / 255.0
     );
+
+    return vec4(srgb_to_linear(color.rgb), color.a);
+}
+
+struct Voxel {
+    uint color;
+    uint material;
+};
+
+bool is_empty(Voxel voxel) {
+    return 0 == voxel.color;
+}
+
+// voxels of one material make a single medium without surfaces between them
+bool is_same_medium(Voxel lhs, Voxel rhs) {
+    return is_empty(lhs) == is_empty(rhs) && lhs.material == rhs.material;
+}
+
+struct OctreeLeaf {
+    Voxel voxel;
+    ivec3 lo;
+    int size;
+};
+
+// `pos` is in octree space which spans [0, 2^depth) along each axis
+OctreeLeaf octree_find_leaf(uint root, int depth, ivec3 pos) {
+    uint index = root;
+    int size = 1 << depth;
+    ivec3 lo = ivec3(0);
+
+    while (nodes[index].kind == NODE_BRANCH) {
+        size /= 2;
+
+        ivec3 upper_half = ivec3(greaterThanEqual(pos, lo + size));
+        lo += size * upper_half;
+        index = nodes[index].data + uint(upper_half.x | (upper_half.y << 1) | (upper_half.z << 2));
+    }
+
+    return OctreeLeaf(Voxel(nodes[index].data, nodes[index].kind >> NODE_MATERIAL_SHIFT), lo, size);
+}
+
+// `pos` is in world space, missing chunks are reported as a single empty leaf
+OctreeLeaf world_find_leaf(ivec3 pos) {
+    ivec3 chunk_pos = pos >> CHUNK_DEPTH;
+    ivec3 chunk_origin = CHUNK_SIZE * chunk_pos;
+    uvec3 table_pos = uvec3(chunk_pos - config.chunk_lo);
+
+    uint table_index = (config.chunk_count.y * table_pos.z + table_pos.y) * config.chunk_count.x + table_pos.x;
+    uint root = chunk_roots[table_index];
+
+    if (NO_CHUNK == root) {
+        return OctreeLeaf(Voxel(0, 0), chunk_origin, CHUNK_SIZE);
+    }
+
+    OctreeLeaf leaf = octree_find_leaf(root, CHUNK_DEPTH, pos - chunk_origin);
+    leaf.lo += chunk_origin;
+
+    return leaf;
+}
+
+int min_axis(vec3 v) {
+    if (v.x < v.y) {
+        return v.x < v.z ? 0 : 2;
+    } else {
+        return v.y < v.z ? 1 : 2;
+    }
+}
+
+int max_axis(vec3 v) {
+    return min_axis(-v);
 }
 
 struct Ray {
@@ -46,11 +252,23 @@ struct RayAabbHit {
     bool has_hit;
 };
 
-RayAabbHit ray_aabb_intersect(vec3 lo, vec3 hi, Ray ray) {
+// distances along the ray to the near and far planes of the box slabs,
+// rays parallel to a slab stay within it all the way if they start in `lo..hi`
+void ray_slab_distances(vec3 lo, vec3 hi, Ray ray, out vec3 tmin, out vec3 tmax) {
     vec3 tbot = ray.inverse_direction * (lo - ray.origin);
     vec3 ttop = ray.inverse_direction * (hi - ray.origin);
-    vec3 tmin = min(ttop, tbot);
-    vec3 tmax = max(ttop, tbot);
+    bvec3 is_parallel = equal(ray.direction, vec3(0.0));
+    bvec3 is_inside = bvec3(uvec3(greaterThanEqual(ray.origin, lo)) & uvec3(lessThan(ray.origin, hi)));
+    vec3 parallel_tmin = mix(vec3(INFINITY), vec3(-INFINITY), is_inside);
+
+    tmin = mix(min(ttop, tbot), parallel_tmin, is_parallel);
+    tmax = mix(max(ttop, tbot), -parallel_tmin, is_parallel);
+}
+
+RayAabbHit ray_aabb_intersect(vec3 lo, vec3 hi, Ray ray) {
+    vec3 tmin, tmax;
+    ray_slab_distances(lo, hi, ray, tmin, tmax);
+
     vec2 t = max(tmin.xx, tmin.yz);
 
     float t0 = max(t.x, t.y);
@@ -62,120 +280,616 @@ RayAabbHit ray_aabb_intersect(vec3 lo, vec3 hi, Ray ray) {
 
 struct RaytraceResult {
     vec4 color;
+    Voxel voxel;
     vec3 position;
     vec3 normal;
     bool has_hit;
+    // leaves visited by the traversal
+    uint steps;
 };
 
-RaytraceResult raytrace(Ray ray) {
-    RayAabbHit aabb_hit = ray_aabb_intersect(vec3(-8.0), vec3(8.0), ray);
+// `medium` is the voxel the ray travels through, the ray hits the first voxel of another medium
+// and leaving the world counts as hitting an empty voxel
+RaytraceResult raytrace(Ray ray, Voxel medium) {
+    ivec3 world_lo = CHUNK_SIZE * config.chunk_lo;
+    ivec3 world_hi = CHUNK_SIZE * (config.chunk_lo + ivec3(config.chunk_count));
+
+    RayAabbHit aabb_hit = ray_aabb_intersect(vec3(world_lo), vec3(world_hi), ray);
 
     if (!aabb_hit.has_hit) {
-        return RaytraceResult(vec4(0.0), ray.origin, vec3(0.0), false);
+        return RaytraceResult(vec4(0.0), Voxel(0, 0), ray.origin, vec3(0.0), false, 0);
     }
 
-    ray.origin += aabb_hit.distance_near * ray.direction;
-
-    float t = 0.0;
-    ivec3 int_pos = ivec3(ray.origin);
+    vec3 origin = ray.origin;
     ivec3 step = ivec3(sign(ray.direction));
 
-    vec3 tdelta = abs(ray.inverse_direction);
-    ivec3 dist_mask = (step + 1) / 2;
-    vec3 dist = dist_mask * (int_pos - ray.origin + 1) + (1 - dist_mask) * (ray.origin - int_pos);
+    float t = max(aabb_hit.distance_near, 0.0);
+    ivec3 int_pos = clamp(ivec3(floor(origin + t * ray.direction)), world_lo, world_hi - 1);
 
-    vec3 tmax = dist * tdelta;
-    vec3 pos = ray.origin;
+    // the ray either starts inside the world or enters it through the face of the latest slab
     int stepped_index = -1;
 
-    while (t <= aabb_hit.distance_far) {
-        vec4 color = get_voxel_color(int_pos);
-        pos = ray.origin + t * ray.direction;
+    if (aabb_hit.distance_near > 0.0) {
+        vec3 tmin, tmax;
+        ray_slab_distances(vec3(world_lo), vec3(world_hi), ray, tmin, tmax);
+
+        stepped_index = max_axis(tmin);
+    }
+
+    bool has_left_world = false;
+    uint n_steps = 0;
 
-        if (vec4(0.0) != color) {
+    for (uint i = 0; i < MAX_TRAVERSAL_STEPS; ++i) {
+        OctreeLeaf leaf = world_find_leaf(int_pos);
+        n_steps += 1;
+
+        if (!is_same_medium(medium, leaf.voxel)) {
             vec3 mask = vec3(stepped_index == 0, stepped_index == 1, stepped_index == 2);
             vec3 normal = -mask * step;
 
-            return RaytraceResult(color, pos, normal, true);
+            return RaytraceResult(
+                unpack_color(leaf.voxel.color), leaf.voxel, ray.origin + t * ray.direction, normal, true, n_steps
+            );
         }
 
-        if (tmax.x < tmax.y) {
-            if (tmax.x < tmax.z) {
-                int_pos.x += step.x;
-                t = tmax.x;
-                tmax.x += tdelta.x;
-                stepped_index = 0;
-            } else {
-                int_pos.z += step.z;
-                t = tmax.z;
-                tmax.z += tdelta.z;
-                stepped_index = 2;
-            }
-        } else {
-            if (tmax.y < tmax.z) {
-                int_pos.y += step.y;
-                t = tmax.y;
-                tmax.y += tdelta.y;
-                stepped_index = 1;
-            } else {
-                int_pos.z += step.z;
-                t = tmax.z;
-                tmax.z += tdelta.z;
-                stepped_index = 2;
+        // skip the whole leaf at once by jumping to its exit face
+        vec3 exit_bound = vec3(leaf.lo + max(step, 0) * leaf.size);
+        vec3 tmax = (exit_bound - origin) * ray.inverse_direction;
+
+        tmax.x = 0 == step.x ? INFINITY : tmax.x;
+        tmax.y = 0 == step.y ? INFINITY : tmax.y;
+        tmax.z = 0 == step.z ? INFINITY : tmax.z;
+
+        stepped_index = min_axis(tmax);
+        t = tmax[stepped_index];
+
+        if (t > aabb_hit.distance_far) {
+            has_left_world = true;
+            break;
+        }
+
+        int_pos = clamp(
+            ivec3(floor(origin + t * ray.direction)),
+            leaf.lo,
+            leaf.lo + leaf.size - 1
+        );
+
+        int_pos[stepped_index] = step[stepped_index] > 0
+            ? leaf.lo[stepped_index] + leaf.size
+            : leaf.lo[stepped_index] - 1;
+
+        if (any(lessThan(int_pos, world_lo)) || any(greaterThanEqual(int_pos, world_hi))) {
+            has_left_world = true;
+            break;
+        }
+    }
+
+    if (has_left_world && !is_empty(medium)) {
+        vec3 mask = vec3(stepped_index == 0, stepped_index == 1, stepped_index == 2);
+
+        return RaytraceResult(
+            vec4(0.0), Voxel(0, 0), ray.origin + t * ray.direction, -mask * step, true, n_steps
+        );
+    }
+
+    return RaytraceResult(vec4(0.0), Voxel(0, 0), ray.origin + t * ray.direction, vec3(0.0), false, n_steps);
+}
+
+// `screen_coord` is in [-1, 1] range with +Y up, `aspect_ratio` is width over height
+Ray camera_ray(vec2 screen_coord, float aspect_ratio) {
+    screen_coord.x *= aspect_ratio;
+
+    vec3 origin;
+    vec3 direction;
+
+    if (PROJECTION_ORTHOGRAPHIC == camera.projection) {
+        origin = camera.position
+            + camera.half_height * (screen_coord.x * camera.right + screen_coord.y * camera.up);
+        direction = camera.forward;
+    } else {
+        origin = camera.position;
+        direction = normalize(camera.forward
+            + screen_coord.x * camera.tan_half_vfov * camera.right
+            + screen_coord.y * camera.tan_half_vfov * camera.up);
+    }
+
+    // start at the near plane, hits behind the far plane are discarded by the caller
+    origin += camera.near * direction;
+
+    return Ray(origin, direction, 1.0 / direction);
+}
+
+bool is_occluded(vec3 origin, vec3 direction, float max_distance) {
+    RaytraceResult result = raytrace(Ray(origin, direction, 1.0 / direction), Voxel(0, 0));
+
+    return result.has_hit && distance(origin, result.position) < max_distance;
+}
+
+float voxel_opacity(RaytraceResult result) {
+    return result.color.a * materials[result.voxel.material].opacity;
+}
+
+// fraction of light passing through the voxels on the way, every transparent surface lets
+// `1 - opacity` of it through and opaque voxels block it all
+float transmittance(vec3 origin, vec3 direction, float max_distance) {
+    Ray ray = Ray(origin, direction, 1.0 / direction);
+    Voxel medium = Voxel(0, 0);
+    float transmitted = 1.0;
+
+    for (uint i = 0; i < MAX_TRANSPARENT_LAYERS; ++i) {
+        RaytraceResult result = raytrace(ray, medium);
+
+        if (!result.has_hit || distance(origin, result.position) >= max_distance) {
+            return transmitted;
+        }
+
+        if (!is_empty(result.voxel)) {
+            transmitted *= 1.0 - voxel_opacity(result);
+
+            if (transmitted <= 0.0) {
+                return 0.0;
             }
         }
+
+        medium = result.voxel;
+        ray.origin = result.position + SURFACE_OFFSET * direction;
     }
 
-    return RaytraceResult(vec4(0.0), pos, vec3(0.0), false);
+    return 0.0;
+}
+
+float index_of_refraction(Voxel voxel) {
+    return is_empty(voxel) ? 1.0 : materials[voxel.material].ior;
+}
+
+// Schlick's approximation
+float fresnel_reflectance(float cos_theta, float eta) {
+    float r0 = (1.0 - eta) / (1.0 + eta);
+    r0 *= r0;
+
+    return r0 + (1.0 - r0) * pow(1.0 - cos_theta, 5.0);
 }
 
-vec3 spherical_to_cartesian(vec3 coords) {
-    return coords.x * vec3(
-            sin(coords.z) * sin(coords.y),
-            cos(coords.z),
-            sin(coords.z) * cos(coords.y)
+// direction of a ray going from `medium` into `voxel` through a surface with `normal` facing the ray,
+// the ray reflects on total internal reflection or, if `use_fresnel` is set, randomly by the Fresnel term,
+// `medium` becomes `voxel` unless the ray reflects
+vec3 pass_surface(vec3 direction, vec3 normal, inout Voxel medium, Voxel voxel, bool use_fresnel) {
+    if (0 == (config.flags & FLAG_REFRACTION)) {
+        medium = voxel;
+        return direction;
+    }
+
+    float eta = index_of_refraction(medium) / index_of_refraction(voxel);
+    vec3 refracted = refract(direction, normal, eta);
+
+    if (vec3(0.0) == refracted) {
+        return reflect(direction, normal);
+    }
+
+    // the cosine is taken on the less dense side
+    float cos_theta = eta > 1.0 ? -dot(refracted, normal) : -dot(direction, normal);
+
+    if (use_fresnel && random_float() < fresnel_reflectance(cos_theta, eta)) {
+        return reflect(direction, normal);
+    }
+
+    medium = voxel;
+    return refracted;
+}
+
+struct LightSample {
+    vec3 direction;
+    float distance;
+};
+
+LightSample light_sample(Light light, vec3 position) {
+    if (LIGHT_DIRECTIONAL == light.kind) {
+        return LightSample(-light.position, INFINITY);
+    }
+
+    vec3 offset = light.position - position;
+    float light_distance = length(offset);
+
+    return LightSample(offset / light_distance, light_distance);
+}
+
+// lights have no falloff and `color` is the radiance reflected by a white surface facing the light,
+// shadow rays start off the surface along the normal so they do not hit the voxel itself
+vec3 direct_lighting(vec3 position, vec3 normal) {
+    vec3 origin = position + SURFACE_OFFSET * normal;
+    bool has_shadows = 0 != (config.flags & FLAG_SHADOWS);
+    vec3 radiance = vec3(0.0);
+
+    for (uint i = 0; i < light_count; ++i) {
+        LightSample light = light_sample(lights[i], origin);
+        float cos_theta = dot(light.direction, normal);
+
+        if (cos_theta <= 0.0) {
+            continue;
+        }
+
+        float visibility = has_shadows ? transmittance(origin, light.direction, light.distance) : 1.0;
+
+        radiance += lights[i].color * cos_theta * visibility;
+    }
+
+    return radiance;
+}
+
+vec3 cosine_sample_hemisphere(vec3 normal) {
+    float phi = 2.0 * PI * random_float();
+    float sin_theta_squared = random_float();
+    float sin_theta = sqrt(sin_theta_squared);
+
+    vec3 tangent = normalize(cross(normal, abs(normal.y) < 0.5 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0)));
+    vec3 bitangent = cross(normal, tangent);
+
+    return normalize(
+        sin_theta * cos(phi) * tangent
+        + sin_theta * sin(phi) * bitangent
+        + sqrt(1.0 - sin_theta_squared) * normal
+    );
+}
+
+// radiance coming from infinitely far away in `direction`
+vec3 environment_radiance(vec3 direction) {
+    switch (environment.kind) {
+    case ENVIRONMENT_GRADIENT:
+        if (direction.y >= 0.0) {
+            return mix(environment.horizon, environment.zenith, sqrt(direction.y));
+        } else {
+            return mix(environment.horizon, environment.ground, sqrt(-direction.y));
+        }
+    case ENVIRONMENT_MAP: {
+        vec2 uv = vec2(
+            atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+            acos(clamp(direction.y, -1.0, 1.0)) / PI
         );
+
+        return environment.intensity
+            * textureLod(sampler2D(environment_map, environment_sampler), uv, 0.0).rgb;
+    }
+    default:
+        return environment.zenith;
+    }
+}
+
+// environment light reflected by a white surface facing along `normal`
+vec3 ambient_radiance(vec3 normal) {
+    int axis = max_axis(abs(normal));
+    return environment.ambient[2 * axis + int(normal[axis] < 0.0)].rgb;
+}
+
+bool is_solid(ivec3 pos) {
+    ivec3 world_lo = CHUNK_SIZE * config.chunk_lo;
+    ivec3 world_hi = CHUNK_SIZE * (config.chunk_lo + ivec3(config.chunk_count));
+
+    if (any(lessThan(pos, world_lo)) || any(greaterThanEqual(pos, world_hi))) {
+        return false;
+    }
+
+    return !is_empty(world_find_leaf(pos).voxel);
+}
+
+// occlusion of the face corners by the 8 voxels around the cell in front of the face,
+// interpolated over the face
+float neighbors_ambient_occlusion(vec3 position, vec3 normal) {
+    int axis = max_axis(abs(normal));
+    int u_axis = (axis + 1) % 3;
+    int v_axis = (axis + 2) % 3;
+
+    ivec3 front = ivec3(floor(position + 0.5 * normal));
+    ivec3 u = ivec3(0);
+    ivec3 v = ivec3(0);
+    u[u_axis] = 1;
+    v[v_axis] = 1;
+
+    float u_sides[2] = float[2](float(is_solid(front - u)), float(is_solid(front + u)));
+    float v_sides[2] = float[2](float(is_solid(front - v)), float(is_solid(front + v)));
+    float corners[4];
+
+    for (int i = 0; i < 4; ++i) {
+        int u_side = i & 1;
+        int v_side = i >> 1;
+        float u_occluded = u_sides[u_side];
+        float v_occluded = v_sides[v_side];
+        float corner_occluded = float(is_solid(front + (2 * u_side - 1) * u + (2 * v_side - 1) * v));
+
+        corners[i] = u_occluded * v_occluded > 0.0
+            ? 0.0
+            : (3.0 - u_occluded - v_occluded - corner_occluded) / 3.0;
+    }
+
+    vec2 face_coord = fract(vec2(position[u_axis], position[v_axis]));
+
+    return mix(
+        mix(corners[0], corners[1], face_coord.x),
+        mix(corners[2], corners[3], face_coord.x),
+        face_coord.y
+    );
+}
+
+// fraction of short cosine-weighted rays escaping the surface
+float traced_ambient_occlusion(vec3 position, vec3 normal) {
+    vec3 origin = position + SURFACE_OFFSET * normal;
+    uint n_escaped = 0;
+
+    for (uint i = 0; i < AMBIENT_OCCLUSION_SAMPLES; ++i) {
+        vec3 direction = cosine_sample_hemisphere(normal);
+
+        if (!is_occluded(origin, direction, AMBIENT_OCCLUSION_DISTANCE)) {
+            n_escaped += 1;
+        }
+    }
+
+    return float(n_escaped) / float(AMBIENT_OCCLUSION_SAMPLES);
+}
+
+float ambient_occlusion(vec3 position, vec3 normal) {
+    // rays starting inside a voxel have no surface to occlude
+    if (vec3(0.0) == normal) {
+        return 1.0;
+    }
+
+    switch (config.ambient_occlusion) {
+    case AMBIENT_OCCLUSION_NEIGHBORS:
+        return neighbors_ambient_occlusion(position, normal);
+    case AMBIENT_OCCLUSION_TRACED:
+        return traced_ambient_occlusion(position, normal);
+    default:
+        return 1.0;
+    }
+}
+
+// light a metal surface reflects towards the viewer, a single reflection ray is traced
+// and widens towards the diffuse lobe with roughness like in `path_trace()`
+vec3 specular_radiance(vec3 position, vec3 normal, vec3 direction, float roughness, Voxel medium) {
+    vec3 reflected = reflect(direction, normal);
+    RaytraceResult result = raytrace(
+        Ray(position + SURFACE_OFFSET * normal, reflected, 1.0 / reflected), medium
+    );
+
+    // reflections of reflections are not traced, reflected voxels are shaded as diffuse
+    vec3 mirror = environment_radiance(reflected);
+
+    if (result.has_hit && !is_empty(result.voxel)) {
+        Material material = materials[result.voxel.material];
+
+        mirror = (direct_lighting(result.position, result.normal) + ambient_radiance(result.normal))
+            * result.color.rgb * material.albedo + material.emission;
+    }
+
+    vec3 diffuse = direct_lighting(position, normal) + ambient_radiance(normal);
+
+    return mix(mirror, diffuse, roughness * roughness);
+}
+
+// one path with next event estimation at every diffuse bounce,
+// passing through a transparent surface counts as a bounce
+vec4 path_trace(Ray ray) {
+    vec3 radiance = vec3(0.0);
+    vec3 throughput = vec3(1.0);
+    Voxel medium = Voxel(0, 0);
+
+    for (uint bounce = 0; bounce <= config.max_bounces; ++bounce) {
+        RaytraceResult result = raytrace(ray, medium);
+
+        if (0 == bounce && result.has_hit
+            && distance(ray.origin, result.position) > camera.far - camera.near)
+        {
+            result.has_hit = false;
+        }
+
+        if (!result.has_hit) {
+            radiance += throughput * environment_radiance(ray.direction);
+            break;
+        }
+
+        // the ray leaves the medium it travels through
+        if (is_empty(result.voxel)) {
+            vec3 direction = pass_surface(ray.direction, result.normal, medium, result.voxel, true);
+            ray = Ray(result.position + SURFACE_OFFSET * direction, direction, 1.0 / direction);
+            continue;
+        }
+
+        Material material = materials[result.voxel.material];
+
+        // emissive voxels are not sampled as lights, paths only find them by hitting them
+        radiance += throughput * material.emission;
+
+        // rays starting inside a voxel have no surface to bounce off
+        if (vec3(0.0) == result.normal) {
+            break;
+        }
+
+        throughput *= result.color.rgb * material.albedo;
+
+        vec3 direction;
+
+        // transparent voxels tint the light going through them
+        if (random_float() >= voxel_opacity(result)) {
+            direction = pass_surface(ray.direction, result.normal, medium, result.voxel, true);
+            ray = Ray(result.position + SURFACE_OFFSET * direction, direction, 1.0 / direction);
+            continue;
+        }
+
+        // metals reflect tinted by the albedo, the reflection widens towards the diffuse lobe with roughness
+        if (random_float() < material.metalness) {
+            vec3 reflected = reflect(ray.direction, result.normal);
+            float spread = material.roughness * material.roughness;
+
+            direction = normalize(mix(reflected, cosine_sample_hemisphere(result.normal), spread));
+        } else {
+            // the cosine-weighted pdf cancels out the cosine and 1/pi of the Lambert BRDF
+            radiance += throughput * direct_lighting(result.position, result.normal);
+            direction = cosine_sample_hemisphere(result.normal);
+        }
+
+        ray = Ray(result.position + SURFACE_OFFSET * result.normal, direction, 1.0 / direction);
+    }
+
+    return vec4(radiance, 1.0);
+}
+
+// blue through green and yellow to red for `t` from 0 to 1
+vec3 heatmap(float t) {
+    t = clamp(t, 0.0, 1.0);
+    return clamp(1.5 - abs(4.0 * t - vec3(3.0, 2.0, 1.0)), 0.0, 1.0);
+}
+
+// distance from `position` on a face with `normal` to the edges of the box on that face
+float distance_to_box_edge(vec3 position, vec3 normal, vec3 lo, float size) {
+    vec3 distances = min(position - lo, lo + size - position);
+    distances[max_axis(abs(normal))] = INFINITY;
+
+    return min(distances.x, min(distances.y, distances.z));
+}
+
+bool is_in_world(ivec3 pos) {
+    ivec3 world_lo = CHUNK_SIZE * config.chunk_lo;
+    ivec3 world_hi = CHUNK_SIZE * (config.chunk_lo + ivec3(config.chunk_count));
+
+    return all(greaterThanEqual(pos, world_lo)) && all(lessThan(pos, world_hi));
+}
+
+// display color of the primary ray in the current debug view
+vec3 debug_color(Ray ray) {
+    RaytraceResult result = raytrace(ray, Voxel(0, 0));
+
+    if (DEBUG_VIEW_STEPS == config.debug_view) {
+        return heatmap(float(result.steps) / DEBUG_MAX_STEPS);
+    }
+
+    if (!result.has_hit) {
+        return vec3(0.0);
+    }
+
+    // voxel the ray hits and the one in front of the hit face
+    ivec3 voxel_pos = ivec3(floor(result.position - 0.5 * result.normal));
+    ivec3 front_pos = ivec3(floor(result.position + 0.5 * result.normal));
+
+    switch (config.debug_view) {
+    case DEBUG_VIEW_NORMALS:
+        return 0.5 * result.normal + 0.5;
+    case DEBUG_VIEW_DEPTH:
+        // logarithmic to tell apart both near and far hits
+        return vec3(1.0 - log(1.0 + distance(ray.origin, result.position)) / log(1.0 + camera.far - camera.near));
+    case DEBUG_VIEW_VOXEL_COORDS:
+        return vec3(voxel_pos & (CHUNK_SIZE - 1)) / float(CHUNK_SIZE - 1);
+    case DEBUG_VIEW_BOUNDARIES: {
+        vec3 chunk_lo = vec3(CHUNK_SIZE * (voxel_pos >> CHUNK_DEPTH));
+
+        if (distance_to_box_edge(result.position, result.normal, chunk_lo, float(CHUNK_SIZE)) < DEBUG_LINE_WIDTH) {
+            return vec3(1.0, 0.0, 0.0);
+        }
+
+        // the empty leaf the ray has come through before hitting the face
+        if (is_in_world(front_pos)) {
+            OctreeLeaf leaf = world_find_leaf(front_pos);
+
+            if (distance_to_box_edge(result.position, result.normal, vec3(leaf.lo), float(leaf.size)) < DEBUG_LINE_WIDTH) {
+                return vec3(0.0, 1.0, 0.0);
+            }
+        }
+
+        // faces along different axes are shaded differently
+        return vec3(0.3 + 0.2 * dot(abs(result.normal), vec3(1.0, 2.0, 3.0)) / 3.0);
+    }
+    default:
+        return vec3(0.0);
+    }
 }
 
 void main() {
     ivec2 index = ivec2(gl_GlobalInvocationID.xy);
 
-    float aspect_ratio = float(config.viewport_size.y) / float(config.viewport_size.x);
-    vec2 screen_coord = 2.0 * vec2(index) / vec2(config.render_texture_size - 1) - 1.0;
+    // the last workgroups overhang textures with sizes not divisible by the workgroup size
+    if (any(greaterThanEqual(uvec2(index), config.render_texture_size))) {
+        return;
+    }
 
-    float camera_vfov = PI / 3.0;
-    vec3 camera_target_pos = vec3(0.0);
-    // distance theta phi
-    vec3 camera_spherical_coords = vec3(16.0, config.time, 0.8);
-    vec3 camera_pos = camera_target_pos + spherical_to_cartesian(camera_spherical_coords);
+    bool is_path_tracing = RENDER_MODE_PATH_TRACE == config.render_mode;
 
-    vec3 camera_direction = normalize(camera_target_pos - camera_pos);
-    vec3 camera_tangent = vec3(cos(camera_spherical_coords.y), 0.0, -sin(camera_spherical_coords.y));
-    vec3 camera_bitangent = cross(camera_direction, camera_tangent);
+    rng_state = pcg_hash(
+        (uint(index.y) * config.render_texture_size.x + uint(index.x)) ^ pcg_hash(config.sample_index)
+    );
 
-    float fov_tan = tan(0.5 * camera_vfov);
-    vec3 ray_direction = normalize(camera_direction
-                + (screen_coord.x / aspect_ratio) * fov_tan * camera_tangent
-                + screen_coord.y * fov_tan * camera_bitangent
-        );
+    // path tracing jitters samples over the pixel for antialiasing
+    vec2 pixel_offset = is_path_tracing ? vec2(random_float(), random_float()) : vec2(0.5);
 
-    Ray ray = Ray(camera_pos, ray_direction, 1.0 / ray_direction);
+    float aspect_ratio = float(config.render_texture_size.x) / float(config.render_texture_size.y);
+    // texture rows go from top to bottom
+    vec2 screen_coord = 2.0 * (vec2(index) + pixel_offset) / vec2(config.render_texture_size) - 1.0;
+    screen_coord.y = -screen_coord.y;
 
-    RaytraceResult result = raytrace(ray);
+    Ray ray = camera_ray(screen_coord, aspect_ratio);
 
-    vec3 light_position = vec3(10.0, 12.0, 16.0);
-    vec4 color;
+    // the screen quad shows debug colors without tonemapping them
+    if (DEBUG_VIEW_OFF != config.debug_view) {
+        imageStore(screen, index, vec4(srgb_to_linear(debug_color(ray)), 1.0));
+        return;
+    }
 
-    if (result.has_hit) {
-        vec3 to_light_direction = normalize(light_position - result.position);
-        float brightness = max(0.0, dot(to_light_direction, result.normal));
+    if (is_path_tracing) {
+        vec4 sum = path_trace(ray);
 
-        color = vec4(brightness * result.color, 1.0);
-    } else {
-        color = vec4(0.0);
+        if (0 != config.sample_index) {
+            sum += imageLoad(accumulation, index);
+        }
+
+        imageStore(accumulation, index, sum);
+        imageStore(screen, index, sum / float(config.sample_index + 1));
+
+        return;
+    }
+
+    // transparent surfaces are blended front to back
+    vec4 color = vec4(0.0);
+    Voxel medium = Voxel(0, 0);
+    float ray_distance = 0.0;
+
+    for (uint layer = 0; layer < MAX_TRANSPARENT_LAYERS && color.a < OPAQUE_ALPHA; ++layer) {
+        RaytraceResult result = raytrace(ray, medium);
+        ray_distance += distance(ray.origin, result.position);
+
+        if (!result.has_hit || ray_distance > camera.far - camera.near) {
+            break;
+        }
+
+        if (!is_empty(result.voxel)) {
+            Material material = materials[result.voxel.material];
+            float opacity = voxel_opacity(result);
+
+            vec3 radiance = direct_lighting(result.position, result.normal)
+                + ambient_occlusion(result.position, result.normal) * ambient_radiance(result.normal);
+
+            // metals replace the diffuse lobe with the reflection
+            if (material.metalness > 0.0) {
+                radiance = mix(
+                    radiance,
+                    specular_radiance(result.position, result.normal, ray.direction, material.roughness, medium),
+                    material.metalness
+                );
+            }
+
+            vec3 surface = radiance * result.color.rgb * material.albedo + material.emission;
+
+            color.rgb += (1.0 - color.a) * opacity * surface;
+            color.a += (1.0 - color.a) * opacity;
+        }
+
+        // rays starting inside a voxel have no surface to pass through
+        if (vec3(0.0) == result.normal) {
+            break;
+        }
+
+        vec3 direction = pass_surface(ray.direction, result.normal, medium, result.voxel, false);
+        ray = Ray(result.position + SURFACE_OFFSET * direction, direction, 1.0 / direction);
     }
 
-    // color = vec4(100.0 * sin(100.0 * config.time), 0.0, 0.0, 1.0);
+    // whatever the surfaces let through comes from the environment
+    color.rgb += (1.0 - color.a) * environment_radiance(ray.direction);
+    color.a = 1.0;
 
     imageStore(screen, index, color);
 }
@@ -0,0 +1,151 @@
+use bytemuck::{Pod, Zeroable};
+use glam::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    Perspective,
+    /// Orthographic projection spanning `height` world units vertically
+    Orthographic {
+        height: f32,
+    },
+}
+
+/// Camera looking along its local `-Z` axis with `+Y` being up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: Quat,
+    /// Vertical field of view in radians
+    pub vfov: f32,
+    pub near: f32,
+    pub far: f32,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::orbit(Vec3::ZERO, 40.0, 0.0, 0.8)
+    }
+}
+
+impl Camera {
+    pub const DEFAULT_VFOV: f32 = PI / 3.0;
+    pub const DEFAULT_NEAR: f32 = 0.1;
+    pub const DEFAULT_FAR: f32 = 1000.0;
+
+    pub fn new(position: Vec3, rotation: Quat) -> Self {
+        Self {
+            position,
+            rotation,
+            vfov: Self::DEFAULT_VFOV,
+            near: Self::DEFAULT_NEAR,
+            far: Self::DEFAULT_FAR,
+            projection: Projection::Perspective,
+        }
+    }
+
+    pub fn look_at(position: Vec3, target: Vec3) -> Self {
+        Self::new(position, Self::look_rotation(target - position))
+    }
+
+    /// Camera looking at `target` from `distance` away where `azimuth` is measured around `+Y`
+    /// starting at `+Z` and `polar` is the angle from `+Y`
+    pub fn orbit(target: Vec3, distance: f32, azimuth: f32, polar: f32) -> Self {
+        let offset = distance
+            * Vec3::new(
+                polar.sin() * azimuth.sin(),
+                polar.cos(),
+                polar.sin() * azimuth.cos(),
+            );
+
+        Self::look_at(target + offset, target)
+    }
+
+    /// Rotation turning `-Z` into `direction` keeping the horizon level
+    pub fn look_rotation(direction: Vec3) -> Quat {
+        let forward = direction.normalize();
+        let right = forward.cross(Vec3::Y).try_normalize().unwrap_or(Vec3::X);
+        let up = right.cross(forward);
+
+        Quat::from_mat3(&Mat3::from_cols(right, up, -forward))
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * Vec3::NEG_Z
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vec3::X
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vec3::Y
+    }
+
+    pub fn to_uniform(&self) -> CameraUniform {
+        let (projection, half_height) = match self.projection {
+            Projection::Perspective => (CameraUniform::PERSPECTIVE, 0.0),
+            Projection::Orthographic { height } => (CameraUniform::ORTHOGRAPHIC, 0.5 * height),
+        };
+
+        CameraUniform {
+            position: self.position,
+            near: self.near,
+            forward: self.forward(),
+            far: self.far,
+            right: self.right(),
+            tan_half_vfov: f32::tan(0.5 * self.vfov),
+            up: self.up(),
+            half_height,
+            projection,
+            _padding: [0; 3],
+        }
+    }
+
+    /// Ray origin and direction for a point on the screen in `[-1, 1]` range with `+Y` up,
+    /// `aspect_ratio` is width over height
+    pub fn ray(&self, screen_coord: Vec2, aspect_ratio: f32) -> (Vec3, Vec3) {
+        let screen_coord = Vec2::new(aspect_ratio * screen_coord.x, screen_coord.y);
+
+        match self.projection {
+            Projection::Perspective => {
+                let fov_tan = f32::tan(0.5 * self.vfov);
+                let direction = (self.forward()
+                    + screen_coord.x * fov_tan * self.right()
+                    + screen_coord.y * fov_tan * self.up())
+                .normalize();
+
+                (self.position, direction)
+            }
+            Projection::Orthographic { height } => {
+                let origin = self.position
+                    + 0.5 * height * (screen_coord.x * self.right() + screen_coord.y * self.up());
+
+                (origin, self.forward())
+            }
+        }
+    }
+}
+
+/// [`Camera`] layout as the compute shader reads it (std140)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct CameraUniform {
+    pub position: Vec3,
+    pub near: f32,
+    pub forward: Vec3,
+    pub far: f32,
+    pub right: Vec3,
+    pub tan_half_vfov: f32,
+    pub up: Vec3,
+    pub half_height: f32,
+    pub projection: u32,
+    pub _padding: [u32; 3],
+}
+
+impl CameraUniform {
+    pub const PERSPECTIVE: u32 = 0;
+    pub const ORTHOGRAPHIC: u32 = 1;
+}
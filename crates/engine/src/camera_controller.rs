@@ -0,0 +1,195 @@
+use crate::camera::Camera;
+use crate::input::Input;
+use glam::*;
+use std::f32::consts::FRAC_PI_2;
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+/// Free-flying camera: WASD to move, Space/Shift to go up and down,
+/// right mouse button drag to look around and Ctrl to speed up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlyController {
+    /// Rotation around `+Y` in radians, zero looks along `-Z`
+    pub yaw: f32,
+    /// Rotation above the horizon in radians
+    pub pitch: f32,
+    /// Units per second
+    pub speed: f32,
+    pub boost_factor: f32,
+    /// Radians per pixel of cursor movement
+    pub sensitivity: f32,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            yaw: 0.0,
+            pitch: 0.0,
+            speed: 10.0,
+            boost_factor: 4.0,
+            sensitivity: 0.003,
+        }
+    }
+}
+
+impl FlyController {
+    pub const MAX_PITCH: f32 = FRAC_PI_2 - 0.001;
+
+    pub fn from_camera(camera: &Camera) -> Self {
+        let forward = camera.forward();
+
+        Self {
+            yaw: f32::atan2(-forward.x, -forward.z),
+            pitch: forward.y.clamp(-1.0, 1.0).asin(),
+            ..Default::default()
+        }
+    }
+
+    pub fn rotation(&self) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0)
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, input: &Input, time_step: f32) {
+        if input.is_button_pressed(MouseButton::Right) {
+            self.yaw -= self.sensitivity * input.cursor_delta.x;
+            self.pitch -= self.sensitivity * input.cursor_delta.y;
+            self.pitch = self.pitch.clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+        }
+
+        camera.rotation = self.rotation();
+
+        let direction = input.axis(KeyCode::KeyS, KeyCode::KeyW) * camera.forward()
+            + input.axis(KeyCode::KeyA, KeyCode::KeyD) * camera.right()
+            + input.axis(KeyCode::ShiftLeft, KeyCode::Space) * Vec3::Y;
+
+        let speed = if input.is_key_pressed(KeyCode::ControlLeft) {
+            self.boost_factor * self.speed
+        } else {
+            self.speed
+        };
+
+        camera.position += time_step * speed * direction.normalize_or_zero();
+    }
+}
+
+/// Camera rotating around a target: left mouse button drag to rotate, scroll to zoom
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitController {
+    pub target: Vec3,
+    pub distance: f32,
+    /// Angle around `+Y` starting at `+Z` in radians
+    pub azimuth: f32,
+    /// Angle from `+Y` in radians
+    pub polar: f32,
+    /// Radians per pixel of cursor movement
+    pub sensitivity: f32,
+    /// Relative distance change per scroll line
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            distance: 40.0,
+            azimuth: 0.0,
+            polar: 0.8,
+            sensitivity: 0.005,
+            zoom_speed: 0.1,
+            min_distance: 1.0,
+        }
+    }
+}
+
+impl OrbitController {
+    pub const MIN_POLAR: f32 = 0.001;
+    pub const MAX_POLAR: f32 = std::f32::consts::PI - 0.001;
+
+    /// Orbit around `target` keeping the current camera position
+    pub fn from_camera(camera: &Camera, target: Vec3) -> Self {
+        let offset = camera.position - target;
+        let distance = offset.length();
+
+        if distance <= f32::EPSILON {
+            return Self {
+                target,
+                ..Default::default()
+            };
+        }
+
+        Self {
+            target,
+            distance,
+            azimuth: f32::atan2(offset.x, offset.z),
+            polar: (offset.y / distance).clamp(-1.0, 1.0).acos(),
+            ..Default::default()
+        }
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, input: &Input) {
+        if input.is_button_pressed(MouseButton::Left) {
+            self.azimuth -= self.sensitivity * input.cursor_delta.x;
+            self.polar -= self.sensitivity * input.cursor_delta.y;
+            self.polar = self.polar.clamp(Self::MIN_POLAR, Self::MAX_POLAR);
+        }
+
+        self.distance *= (1.0 - self.zoom_speed).powf(input.scroll_delta);
+        self.distance = self.distance.max(self.min_distance);
+
+        self.apply(camera);
+    }
+
+    /// Places the camera on the orbit
+    pub fn apply(&self, camera: &mut Camera) {
+        let orbit = Camera::orbit(self.target, self.distance, self.azimuth, self.polar);
+
+        camera.position = orbit.position;
+        camera.rotation = orbit.rotation;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraController {
+    Fly {
+        controller: FlyController,
+        /// Target of the orbit mode to return to
+        orbit_target: Vec3,
+    },
+    Orbit(OrbitController),
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self::Orbit(OrbitController::default())
+    }
+}
+
+impl CameraController {
+    pub const TOGGLE_KEY: KeyCode = KeyCode::Tab;
+
+    pub fn update(&mut self, camera: &mut Camera, input: &Input, time_step: f32) {
+        if input.is_key_just_pressed(Self::TOGGLE_KEY) {
+            self.toggle(camera);
+        }
+
+        match self {
+            Self::Fly { controller, .. } => controller.update(camera, input, time_step),
+            Self::Orbit(controller) => controller.update(camera, input),
+        }
+    }
+
+    /// Switches between fly and orbit modes without moving the camera,
+    /// orbit mode rotates around the target it had before switching to fly mode
+    pub fn toggle(&mut self, camera: &Camera) {
+        *self = match *self {
+            Self::Fly { orbit_target, .. } => {
+                Self::Orbit(OrbitController::from_camera(camera, orbit_target))
+            }
+            Self::Orbit(orbit) => Self::Fly {
+                controller: FlyController::from_camera(camera),
+                orbit_target: orbit.target,
+            },
+        };
+    }
+}
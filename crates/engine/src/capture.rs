@@ -0,0 +1,194 @@
+use crate::context::RenderContext;
+use glam::*;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+/// CPU-side copy of an RGBA8 texture with tightly packed rows
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Image {
+    pub size: UVec2,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub const BYTES_PER_PIXEL: u32 = 4;
+
+    /// Copies the texture into a mapped buffer and waits for the device to finish,
+    /// fails if the buffer cannot be mapped, e.g. after the device has been lost.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the texture is not a 4-byte per pixel texture with `COPY_SRC` usage.
+    pub fn from_texture(
+        context: &RenderContext,
+        texture: &wgpu::Texture,
+    ) -> Result<Self, wgpu::BufferAsyncError> {
+        assert_eq!(
+            texture.format().block_copy_size(None),
+            Some(Self::BYTES_PER_PIXEL),
+            "only 4-byte per pixel textures can be read back",
+        );
+
+        let size = UVec2::new(texture.width(), texture.height());
+        let unpadded_bytes_per_row = Self::BYTES_PER_PIXEL * size.x;
+        let padded_bytes_per_row =
+            unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("texture-readback"),
+            size: padded_bytes_per_row as u64 * size.y as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("texture-readback"),
+            });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.y),
+                },
+            },
+            texture.size(),
+        );
+
+        context.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            // the receiver outlives the wait below
+            let _ = sender.send(result);
+        });
+        context.device.poll(wgpu::Maintain::Wait);
+
+        // a dropped callback never mapped the buffer
+        receiver.recv().unwrap_or(Err(wgpu::BufferAsyncError))?;
+
+        let pixels = slice
+            .get_mapped_range()
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect();
+
+        buffer.unmap();
+
+        Ok(Self { size, pixels })
+    }
+
+    /// Saves the image picking the format by the file extension
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveImageError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let write = match extension.as_str() {
+            "png" => Self::write_png,
+            "ppm" => Self::write_ppm,
+            _ => return Err(SaveImageError::UnsupportedFormat(extension)),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        write(self, &mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn write_png(&self, writer: &mut dyn Write) -> Result<(), SaveImageError> {
+        let mut encoder = png::Encoder::new(writer, self.size.x, self.size.y);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Writes binary RGB PPM dropping the alpha channel
+    pub fn write_ppm(&self, writer: &mut dyn Write) -> Result<(), SaveImageError> {
+        write!(writer, "P6\n{} {}\n255\n", self.size.x, self.size.y)?;
+
+        for pixel in self.pixels.chunks_exact(Self::BYTES_PER_PIXEL as usize) {
+            writer.write_all(&pixel[..3])?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Frame sequence written to `dir` with the time advanced by a fixed `time_step` per frame,
+/// so recordings do not depend on how fast frames are rendered and saved
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub dir: PathBuf,
+    /// Simulated seconds per frame
+    pub time_step: f32,
+    pub n_frames: u32,
+}
+
+impl Recording {
+    /// Creates `dir` if needed
+    pub fn new(dir: impl Into<PathBuf>, fps: f32) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            time_step: fps.recip(),
+            n_frames: 0,
+        })
+    }
+
+    /// Simulated time of the next frame
+    pub fn time(&self) -> f32 {
+        self.n_frames as f32 * self.time_step
+    }
+
+    pub fn frame_path(&self) -> PathBuf {
+        self.dir.join(format!("frame-{:05}.png", self.n_frames))
+    }
+}
+
+/// `<prefix>-<milliseconds since the Unix epoch>` to keep captures from overwriting each other
+pub fn timestamped_name(prefix: &str) -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    format!("{prefix}-{millis}")
+}
+
+#[derive(Debug, Error)]
+pub enum SaveImageError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+    #[error("unsupported image format '{0}', expected 'png' or 'ppm'")]
+    UnsupportedFormat(String),
+    #[error("failed to read the image back from the GPU")]
+    Readback(#[from] wgpu::BufferAsyncError),
+}
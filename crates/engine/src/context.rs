@@ -1,11 +1,10 @@
 use crate::util::default;
-use bytemuck::{Pod, Zeroable};
-use glam::*;
+use error_stack::{Report, ResultExt as _};
 use pollster::FutureExt as _;
-use std::{borrow::Cow, sync::Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
-use tracing::error;
-use wgpu::util::DeviceExt as _;
+use tracing::{debug, error};
 use winit::dpi::PhysicalSize;
 
 pub use wgpu::{Adapter, Device, Instance, Queue, Surface};
@@ -13,14 +12,144 @@ pub use wgpu::{Adapter, Device, Instance, Queue, Surface};
 #[derive(Clone)]
 pub struct RenderContext {
     pub instance: Arc<Instance>,
-    pub surface: Arc<Surface<'static>>,
+    pub surface: Option<Arc<Surface<'static>>>,
     pub adapter: Arc<Adapter>,
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
+    /// Set from the device lost callback, the context has to be re-created then
+    pub device_lost: Arc<AtomicBool>,
+    /// Applied whenever the surface is configured
+    pub present_mode: Arc<Mutex<wgpu::PresentMode>>,
+}
+
+/// How [`RenderContext`] picks its adapter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContextOptions {
+    /// Tried one by one in [`RenderContext::BACKEND_PRIORITY`] order
+    pub backends: wgpu::Backends,
+    /// Use the uniform buffer fallback even if push constants are supported
+    pub disable_push_constants: bool,
+}
+
+impl ContextOptions {
+    pub const DEFAULT_BACKENDS: wgpu::Backends = wgpu::Backends::PRIMARY.union(wgpu::Backends::GL);
+
+    /// Backends from `WGPU_BACKEND`, e.g. `vulkan,gl`, or [`ContextOptions::DEFAULT_BACKENDS`]
+    pub fn from_env() -> Self {
+        Self {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(Self::DEFAULT_BACKENDS),
+            ..default()
+        }
+    }
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            backends: Self::DEFAULT_BACKENDS,
+            disable_push_constants: false,
+        }
+    }
 }
 
 impl RenderContext {
-    pub fn new(window: &Arc<winit::window::Window>) -> Self {
+    pub const BACKEND_PRIORITY: [wgpu::Backends; 4] = [
+        wgpu::Backends::VULKAN,
+        wgpu::Backends::METAL,
+        wgpu::Backends::DX12,
+        wgpu::Backends::GL,
+    ];
+    pub const REQUIRED_FEATURES: wgpu::Features =
+        wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+    /// Timestamps are optional, the GPU profiler is unavailable without them
+    pub const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::TIMESTAMP_QUERY;
+    pub const DEVICE_LABEL: &'static str = "raytrace-device";
+    /// Push constant bytes required; adapters with a lower limit use uniform buffers instead
+    pub const PUSH_CONSTANT_SIZE: u32 = 128;
+
+    /// Whether the device is created with push constants for `adapter`
+    pub fn uses_push_constants(adapter: &Adapter, options: &ContextOptions) -> bool {
+        !options.disable_push_constants
+            && adapter.features().contains(wgpu::Features::PUSH_CONSTANTS)
+            && adapter.limits().max_push_constant_size >= Self::PUSH_CONSTANT_SIZE
+    }
+
+    // TODO(hack3rmann): require better limits as needed
+    pub fn required_limits(adapter: &Adapter, options: &ContextOptions) -> wgpu::Limits {
+        let max_push_constant_size = match Self::uses_push_constants(adapter, options) {
+            true => Self::PUSH_CONSTANT_SIZE,
+            false => 0,
+        };
+
+        wgpu::Limits {
+            max_push_constant_size,
+            ..wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits())
+        }
+    }
+
+    pub fn supports_push_constants(&self) -> bool {
+        self.device
+            .features()
+            .contains(wgpu::Features::PUSH_CONSTANTS)
+    }
+
+    pub fn new(
+        window: &Arc<winit::window::Window>,
+        options: &ContextOptions,
+    ) -> Result<Self, Report<RenderContextError>> {
+        let instance = Self::create_instance(options.backends);
+
+        let surface = instance
+            .create_surface(Arc::clone(window))
+            .change_context(RenderContextError::Surface)?;
+
+        let adapter = Self::select_adapter(&instance, options, Some(&surface))?;
+        let (device, queue) = Self::request_device(&adapter, options)?;
+        let device_lost = Self::watch_device_loss(&device);
+
+        let window_size = window.inner_size();
+
+        let present_mode = wgpu::PresentMode::AutoVsync;
+
+        let surface_config = Self::surface_config(&surface, &adapter, window_size, present_mode)
+            .ok_or_else(|| RenderContextError::UnsupportedSurface {
+                adapter: adapter.get_info().name,
+            })
+            .attach_printable_lazy(|| format!("window size is {window_size:?}"))?;
+
+        surface.configure(&device, &surface_config);
+
+        Ok(Self {
+            instance: Arc::new(instance),
+            surface: Some(Arc::new(surface)),
+            adapter: Arc::new(adapter),
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            device_lost,
+            present_mode: Arc::new(Mutex::new(present_mode)),
+        })
+    }
+
+    /// Creates a context without any surface to render offscreen
+    pub fn new_headless(options: &ContextOptions) -> Result<Self, Report<RenderContextError>> {
+        let instance = Self::create_instance(options.backends);
+
+        let adapter = Self::select_adapter(&instance, options, None)?;
+        let (device, queue) = Self::request_device(&adapter, options)?;
+        let device_lost = Self::watch_device_loss(&device);
+
+        Ok(Self {
+            instance: Arc::new(instance),
+            surface: None,
+            adapter: Arc::new(adapter),
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            device_lost,
+            present_mode: Arc::new(Mutex::new(wgpu::PresentMode::AutoVsync)),
+        })
+    }
+
+    fn create_instance(backends: wgpu::Backends) -> Instance {
         #[cfg(debug_assertions)]
         let flags = wgpu::InstanceFlags::DEBUG
             | wgpu::InstanceFlags::VALIDATION
@@ -29,228 +158,314 @@ impl RenderContext {
         #[cfg(not(debug_assertions))]
         let flags = wgpu::InstanceFlags::empty();
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::VULKAN,
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
             flags,
             // TODO(hack3rmann): Support for DirectX12 DCX compiler
             // and ship the program with additional dlls
             dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
             gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
-        });
+        })
+    }
+
+    /// Picks the first suitable adapter going through the backends in
+    /// [`RenderContext::BACKEND_PRIORITY`] order, hardware adapters first
+    fn select_adapter(
+        instance: &Instance,
+        options: &ContextOptions,
+        surface: Option<&Surface<'_>>,
+    ) -> Result<Adapter, Report<RenderContextError>> {
+        let mut rejections = None::<Report<RenderContextError>>;
+
+        for backend in Self::BACKEND_PRIORITY {
+            if !options.backends.contains(backend) {
+                continue;
+            }
 
-        let surface = instance.create_surface(Arc::clone(window)).unwrap();
+            let mut adapters = instance.enumerate_adapters(backend);
+            adapters.sort_by_key(|adapter| device_type_rank(adapter.get_info().device_type));
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
+            for adapter in adapters {
+                let result = match surface {
+                    Some(surface) if !adapter.is_surface_supported(surface) => {
+                        Err(Report::new(RenderContextError::UnsupportedSurface {
+                            adapter: adapter.get_info().name,
+                        }))
+                    }
+                    _ => Self::check_adapter(&adapter, options),
+                };
+
+                let report = match result {
+                    Ok(()) => return Ok(adapter),
+                    Err(report) => report,
+                };
+
+                match rejections.as_mut() {
+                    Some(rejections) => rejections.extend_one(report),
+                    None => rejections = Some(report),
+                }
+            }
+        }
+
+        let backends = options.backends;
+
+        match rejections {
+            Some(rejections) => Err(rejections.change_context(RenderContextError::NoAdapter)),
+            None => Err(Report::new(RenderContextError::NoAdapter)),
+        }
+        .attach_printable_lazy(|| format!("tried backends {backends:?}"))
+    }
+
+    /// Checks the adapter against the required features and limits
+    /// to report what exactly is missing
+    fn check_adapter(
+        adapter: &Adapter,
+        options: &ContextOptions,
+    ) -> Result<(), Report<RenderContextError>> {
+        let info = adapter.get_info();
+        let missing_features = Self::REQUIRED_FEATURES - adapter.features();
+
+        if !missing_features.is_empty() {
+            return Err(Report::new(RenderContextError::MissingFeatures {
+                adapter: info.name.clone(),
+                missing: missing_features,
             })
-            .block_on()
-            .unwrap();
+            .attach_printable(format!("adapter: {info:?}")));
+        }
+
+        let mut report = None::<Report<RenderContextError>>;
+
+        Self::required_limits(adapter, options).check_limits_with_fail_fn(
+            &adapter.limits(),
+            false,
+            |name, required, supported| {
+                let error = RenderContextError::LimitTooLow {
+                    adapter: info.name.clone(),
+                    name,
+                    required,
+                    supported,
+                };
+
+                match report.as_mut() {
+                    Some(report) => report.extend_one(Report::new(error)),
+                    None => report = Some(Report::new(error)),
+                }
+            },
+        );
+
+        match report {
+            Some(report) => Err(report.attach_printable(format!("adapter: {info:?}"))),
+            None => Ok(()),
+        }
+    }
+
+    fn request_device(
+        adapter: &Adapter,
+        options: &ContextOptions,
+    ) -> Result<(Device, Queue), Report<RenderContextError>> {
+        let info = adapter.get_info();
 
-        let (device, queue) = adapter
+        let push_constants = match Self::uses_push_constants(adapter, options) {
+            true => wgpu::Features::PUSH_CONSTANTS,
+            false => wgpu::Features::empty(),
+        };
+
+        debug!(adapter = ?info, push_constants = !push_constants.is_empty(), "adapter selected");
+
+        adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    label: Some("raytrace-device"),
-                    required_features: wgpu::Features::PUSH_CONSTANTS
-                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-                    // TODO(hack3rmann): require better limits as needed
-                    required_limits: wgpu::Limits {
-                        max_push_constant_size: 128,
-                        ..default()
-                    },
+                    label: Some(Self::DEVICE_LABEL),
+                    required_features: Self::REQUIRED_FEATURES
+                        | push_constants
+                        | (adapter.features() & Self::OPTIONAL_FEATURES),
+                    required_limits: Self::required_limits(adapter, options),
                     memory_hints: wgpu::MemoryHints::Performance,
                 },
                 None,
             )
             .block_on()
-            .unwrap();
+            .change_context_lazy(|| RenderContextError::RequestDevice {
+                adapter: info.name.clone(),
+            })
+            .attach_printable_lazy(|| format!("adapter: {info:?}"))
+    }
 
-        let window_size = window.inner_size();
+    /// Raises the returned flag once the device is lost, the callback only runs on polls.
+    /// Errors are logged instead of panicking, since using a device lost since the last poll
+    /// fails before the flag is raised
+    fn watch_device_loss(device: &Device) -> Arc<AtomicBool> {
+        let device_lost = Arc::new(AtomicBool::new(false));
 
-        let surface_config = surface
-            .get_default_config(&adapter, window_size.width, window_size.height)
-            .unwrap();
+        device.set_device_lost_callback({
+            let device_lost = Arc::clone(&device_lost);
 
-        surface.configure(&device, &surface_config);
+            move |reason, message| match reason {
+                wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::Destroyed => {
+                    error!(?reason, message, "device lost");
+                    device_lost.store(true, Ordering::Release);
+                }
+                // the context itself is gone
+                wgpu::DeviceLostReason::Dropped | wgpu::DeviceLostReason::ReplacedCallback => {}
+            }
+        });
 
-        Self {
-            instance: Arc::new(instance),
-            surface: Arc::new(surface),
-            adapter: Arc::new(adapter),
-            device: Arc::new(device),
-            queue: Arc::new(queue),
+        device.on_uncaptured_error({
+            let device_lost = Arc::clone(&device_lost);
+
+            Box::new(move |err| match device_lost.load(Ordering::Acquire) {
+                true => debug!(%err, "error after the device loss"),
+                false => error!(%err, "uncaptured wgpu error"),
+            })
+        });
+
+        device_lost
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Acquire)
+    }
+
+    /// Polls the device to run the lost callback if the device has been lost since the last poll
+    pub fn poll_device_lost(&self) -> bool {
+        self.device.poll(wgpu::Maintain::Poll);
+        self.is_device_lost()
+    }
+
+    /// Adapters of all backends with what they lack to run the renderer, one per line
+    pub fn diagnose_adapters() -> String {
+        let options = ContextOptions {
+            backends: wgpu::Backends::all(),
+            ..default()
+        };
+
+        let instance = Self::create_instance(options.backends);
+        let adapters = instance.enumerate_adapters(options.backends);
+
+        if adapters.is_empty() {
+            return "no adapters found on any backend, check the graphics drivers".to_owned();
         }
+
+        adapters
+            .iter()
+            .map(|adapter| {
+                let info = adapter.get_info();
+
+                let status = match Self::check_adapter(adapter, &options) {
+                    Ok(()) if Self::uses_push_constants(adapter, &options) => "suitable".to_owned(),
+                    Ok(()) => "suitable with uniform buffers instead of push constants".to_owned(),
+                    Err(report) => report
+                        .frames()
+                        .filter_map(|frame| frame.downcast_ref::<RenderContextError>())
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                };
+
+                format!(
+                    "{} ({:?}, {:?}, driver {} {}): {status}\n    features: {:?}",
+                    info.name,
+                    info.backend,
+                    info.device_type,
+                    info.driver,
+                    info.driver_info,
+                    adapter.features(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 
-    pub fn resize(&self, viewport_size: PhysicalSize<u32>) -> Result<(), SurfaceUnsupported> {
-        let config = self
-            .surface
-            .get_default_config(&self.adapter, viewport_size.width, viewport_size.height)
-            .ok_or(SurfaceUnsupported)?;
+    /// Default configuration with an `Srgb` format if the surface supports one,
+    /// so that the hardware encodes the linear output
+    fn surface_config(
+        surface: &Surface<'_>,
+        adapter: &Adapter,
+        size: PhysicalSize<u32>,
+        present_mode: wgpu::PresentMode,
+    ) -> Option<wgpu::SurfaceConfiguration> {
+        let mut config = surface.get_default_config(adapter, size.width, size.height)?;
+        config.present_mode = present_mode;
+        let srgb_format = config.format.add_srgb_suffix();
 
-        self.surface.configure(&self.device, &config);
+        if surface
+            .get_capabilities(adapter)
+            .formats
+            .contains(&srgb_format)
+        {
+            config.format = srgb_format;
+        }
 
-        Ok(())
+        Some(config)
     }
 
-    pub fn draw_demo(&self) {
-        let Ok(cur_texture) = self.surface.get_current_texture() else {
-            error!("no next swapchain texture");
-            return;
+    pub fn resize(&self, viewport_size: PhysicalSize<u32>) -> Result<(), SurfaceUnsupported> {
+        let Some(surface) = self.surface.as_ref() else {
+            return Ok(());
         };
 
-        let viewport_size = {
-            let extent = cur_texture.texture.size();
-            UVec2::new(extent.width, extent.height)
-        };
+        let present_mode = *self.present_mode.lock().unwrap();
 
-        let view = cur_texture.texture.create_view(&default());
-        let mut encoder = self.device.create_command_encoder(&default());
+        let config = Self::surface_config(surface, &self.adapter, viewport_size, present_mode)
+            .ok_or(SurfaceUnsupported)?;
 
-        {
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("rainbow-triangle"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            #[repr(C)]
-            #[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
-            struct Vertex {
-                position: Vec2,
-                color: Vec3,
-            }
+        surface.configure(&self.device, &config);
 
-            let vertices = [
-                Vertex {
-                    position: Vec2::new(-0.5, -f32::sqrt(3.0) / 6.0),
-                    color: Vec3::X,
-                },
-                Vertex {
-                    position: Vec2::new(0.5, -f32::sqrt(3.0) / 6.0),
-                    color: Vec3::Y,
-                },
-                Vertex {
-                    position: Vec2::new(0.0, f32::sqrt(3.0) / 3.0),
-                    color: Vec3::Z,
-                },
-            ];
-
-            let vertex_buffer = self
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    contents: bytemuck::cast_slice(&vertices),
-                    label: None,
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-
-            let pipeline_layout =
-                self.device
-                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: None,
-                        bind_group_layouts: &[],
-                        push_constant_ranges: &[wgpu::PushConstantRange {
-                            stages: wgpu::ShaderStages::VERTEX,
-                            range: 0..std::mem::size_of::<UVec2>() as u32,
-                        }],
-                    });
-
-            let vertex_shader = self
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: None,
-                    source: wgpu::ShaderSource::Glsl {
-                        shader: Cow::Borrowed(include_str!(
-                            "../assets/shaders/triangle-vertex.glsl"
-                        )),
-                        stage: wgpu::naga::ShaderStage::Vertex,
-                        defines: default(),
-                    },
-                });
-
-            let fragment_shader = self
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: None,
-                    source: wgpu::ShaderSource::Glsl {
-                        shader: Cow::Borrowed(include_str!(
-                            "../assets/shaders/triangle-fragment.glsl"
-                        )),
-                        stage: wgpu::naga::ShaderStage::Fragment,
-                        defines: default(),
-                    },
-                });
-
-            let pipeline = self
-                .device
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: None,
-                    layout: Some(&pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: &vertex_shader,
-                        entry_point: Some("main"),
-                        compilation_options: default(),
-                        buffers: &[wgpu::VertexBufferLayout {
-                            array_stride: std::mem::size_of::<Vertex>() as u64,
-                            step_mode: wgpu::VertexStepMode::Vertex,
-                            attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x3],
-                        }],
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &fragment_shader,
-                        entry_point: Some("main"),
-                        compilation_options: default(),
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: cur_texture.texture.format(),
-                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                    }),
-                    cache: None,
-                    primitive: wgpu::PrimitiveState {
-                        topology: wgpu::PrimitiveTopology::TriangleList,
-                        strip_index_format: None,
-                        front_face: wgpu::FrontFace::Ccw,
-                        cull_mode: Some(wgpu::Face::Back),
-                        unclipped_depth: false,
-                        polygon_mode: wgpu::PolygonMode::Fill,
-                        conservative: false,
-                    },
-                    depth_stencil: None,
-                    multisample: wgpu::MultisampleState {
-                        count: 1,
-                        mask: !0,
-                        alpha_to_coverage_enabled: false,
-                    },
-                    multiview: None,
-                });
-
-            pass.set_pipeline(&pipeline);
-            pass.set_push_constants(
-                wgpu::ShaderStages::VERTEX,
-                0,
-                bytemuck::bytes_of(&viewport_size),
-            );
-            pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-            pass.draw(0..vertices.len() as u32, 0..1);
+        Ok(())
+    }
+
+    /// Reconfigures the surface if the present mode changes, `Auto*` modes fall back
+    /// to the ones the surface supports
+    pub fn set_present_mode(
+        &self,
+        present_mode: wgpu::PresentMode,
+        viewport_size: PhysicalSize<u32>,
+    ) -> Result<(), SurfaceUnsupported> {
+        let previous = std::mem::replace(&mut *self.present_mode.lock().unwrap(), present_mode);
+
+        if previous == present_mode {
+            return Ok(());
         }
 
-        self.queue.submit([encoder.finish()]);
-        cur_texture.present();
+        self.resize(viewport_size)
     }
 }
 
 #[derive(Debug, Error)]
 #[error("surface is unsupported")]
 pub struct SurfaceUnsupported;
+
+#[derive(Debug, Error)]
+pub enum RenderContextError {
+    #[error("failed to create a surface for the window")]
+    Surface,
+    #[error("no suitable graphics adapter found")]
+    NoAdapter,
+    #[error("adapter '{adapter}' lacks required features {missing:?}")]
+    MissingFeatures {
+        adapter: String,
+        missing: wgpu::Features,
+    },
+    #[error("adapter '{adapter}' supports {name} of {supported}, at least {required} is required")]
+    LimitTooLow {
+        adapter: String,
+        name: &'static str,
+        required: u64,
+        supported: u64,
+    },
+    #[error("failed to request a device from adapter '{adapter}'")]
+    RequestDevice { adapter: String },
+    #[error("adapter '{adapter}' cannot present to the window surface")]
+    UnsupportedSurface { adapter: String },
+}
+
+/// Lower is preferred
+fn device_type_rank(device_type: wgpu::DeviceType) -> u32 {
+    match device_type {
+        wgpu::DeviceType::DiscreteGpu => 0,
+        wgpu::DeviceType::IntegratedGpu => 1,
+        wgpu::DeviceType::VirtualGpu => 2,
+        wgpu::DeviceType::Other => 3,
+        wgpu::DeviceType::Cpu => 4,
+    }
+}
@@ -0,0 +1,180 @@
+use crate::voxels_demo::VoxelsDemo;
+
+/// Picks a render scale holding the frame time around a target.
+///
+/// Frame times are smoothed with an exponential moving average and the scale
+/// only changes once the average leaves the `tolerance` band around the target,
+/// after a change the average is reset and nothing changes for `cooldown_frames`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DynamicResolution {
+    pub enabled: bool,
+    /// Target frame time in seconds
+    pub target_frame_time: f32,
+    /// Allowed relative deviation of the frame time from the target
+    pub tolerance: f32,
+    /// Weight of the newest frame in the moving average
+    pub smoothing: f32,
+    pub cooldown_frames: u32,
+    /// Largest relative change of the render scale at once
+    pub max_step: f32,
+    pub smoothed_frame_time: Option<f32>,
+    pub frames_since_change: u32,
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        Self::new(60.0)
+    }
+}
+
+impl DynamicResolution {
+    pub fn new(target_fps: f32) -> Self {
+        Self {
+            enabled: false,
+            target_frame_time: 1.0 / target_fps,
+            tolerance: 0.15,
+            smoothing: 0.1,
+            cooldown_frames: 30,
+            max_step: 0.25,
+            smoothed_frame_time: None,
+            frames_since_change: 0,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.smoothed_frame_time = None;
+        self.frames_since_change = 0;
+    }
+
+    /// Feeds the last frame time in seconds and returns a new render scale if it should change.
+    /// Frames blocked on vsync never get faster than the refresh rate, so the viewer presents
+    /// without vsync while this is enabled
+    pub fn update(&mut self, frame_time: f32, render_scale: f32) -> Option<f32> {
+        if !self.enabled || frame_time <= 0.0 {
+            return None;
+        }
+
+        let smoothed_frame_time = match self.smoothed_frame_time {
+            None => frame_time,
+            Some(average) => average + self.smoothing * (frame_time - average),
+        };
+
+        self.smoothed_frame_time = Some(smoothed_frame_time);
+        self.frames_since_change += 1;
+
+        if self.frames_since_change < self.cooldown_frames {
+            return None;
+        }
+
+        let ratio = self.target_frame_time / smoothed_frame_time;
+
+        if (1.0 - self.tolerance..=1.0 + self.tolerance).contains(&ratio) {
+            return None;
+        }
+
+        // the raytracing cost is proportional to the pixel count, i.e. to the squared scale
+        let step = ratio.sqrt().clamp(1.0 - self.max_step, 1.0 + self.max_step);
+
+        let new_scale =
+            (step * render_scale).clamp(VoxelsDemo::MIN_RENDER_SCALE, VoxelsDemo::MAX_RENDER_SCALE);
+
+        if new_scale == render_scale {
+            return None;
+        }
+
+        self.reset();
+
+        Some(new_scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled(cooldown_frames: u32) -> DynamicResolution {
+        let mut dynamic_resolution = DynamicResolution {
+            cooldown_frames,
+            ..DynamicResolution::new(50.0)
+        };
+        dynamic_resolution.set_enabled(true);
+
+        dynamic_resolution
+    }
+
+    #[test]
+    fn disabled_keeps_the_scale() {
+        let mut dynamic_resolution = DynamicResolution::new(50.0);
+
+        assert_eq!(None, dynamic_resolution.update(1.0, 1.0));
+        assert_eq!(None, dynamic_resolution.smoothed_frame_time);
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut dynamic_resolution = enabled(u32::MAX);
+
+        // the first frame starts the average
+        dynamic_resolution.update(0.02, 1.0);
+        assert_eq!(Some(0.02), dynamic_resolution.smoothed_frame_time);
+
+        dynamic_resolution.update(0.03, 1.0);
+        let average = dynamic_resolution.smoothed_frame_time.unwrap();
+        assert!((average - 0.021).abs() < 1.0e-6, "{average}");
+
+        // a single spike barely moves the average
+        dynamic_resolution.update(1.0, 1.0);
+        let average = dynamic_resolution.smoothed_frame_time.unwrap();
+        assert!((average - 0.1189).abs() < 1.0e-6, "{average}");
+    }
+
+    #[test]
+    fn tolerance_band() {
+        let mut dynamic_resolution = enabled(1);
+
+        // within 15% of the 20ms target
+        for frame_time in [0.02, 0.018, 0.0225, 0.02] {
+            assert_eq!(None, dynamic_resolution.update(frame_time, 1.0));
+        }
+
+        dynamic_resolution.reset();
+
+        // twice as slow halves the pixel count, the step is limited to 25%
+        assert_eq!(Some(0.75), dynamic_resolution.update(0.04, 1.0));
+
+        // four times as fast would double the scale, limited as well
+        assert_eq!(Some(1.25), dynamic_resolution.update(0.005, 1.0));
+
+        // clamped to the supported range
+        assert_eq!(
+            None,
+            dynamic_resolution.update(0.005, VoxelsDemo::MAX_RENDER_SCALE)
+        );
+    }
+
+    #[test]
+    fn cooldown() {
+        let mut dynamic_resolution = enabled(30);
+
+        for _ in 1..30 {
+            assert_eq!(None, dynamic_resolution.update(0.04, 1.0));
+        }
+
+        assert_eq!(Some(0.75), dynamic_resolution.update(0.04, 1.0));
+
+        // the change resets the average and waits for the cooldown again
+        assert_eq!(None, dynamic_resolution.smoothed_frame_time);
+        assert_eq!(0, dynamic_resolution.frames_since_change);
+
+        for _ in 1..30 {
+            assert_eq!(None, dynamic_resolution.update(0.04, 0.75));
+        }
+
+        assert_eq!(Some(0.5625), dynamic_resolution.update(0.04, 0.75));
+    }
+}
@@ -0,0 +1,253 @@
+use bytemuck::{Pod, Zeroable};
+use glam::*;
+use std::f32::consts::PI;
+use std::path::Path;
+
+/// Radiance coming from infinitely far away, seen in the background
+/// and lighting the surfaces through rays leaving the world
+#[derive(Clone, Debug, PartialEq)]
+pub enum Environment {
+    Uniform(Vec3),
+    /// Sky fading from `horizon` up to `zenith` and down to `ground`
+    Gradient {
+        zenith: Vec3,
+        horizon: Vec3,
+        ground: Vec3,
+    },
+    Map(EnvironmentMap),
+}
+
+impl Environment {
+    pub const UNIFORM: u32 = 0;
+    pub const GRADIENT: u32 = 1;
+    pub const MAP: u32 = 2;
+
+    /// Samples per axis of the grid [`Environment::ambient`] averages over
+    pub const AMBIENT_SAMPLES: u32 = 32;
+
+    /// Radiance coming from `direction`, same as `environment_radiance()` in the compute shader
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Self::Uniform(color) => *color,
+            Self::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                if direction.y >= 0.0 {
+                    horizon.lerp(*zenith, direction.y.sqrt())
+                } else {
+                    horizon.lerp(*ground, (-direction.y).sqrt())
+                }
+            }
+            Self::Map(map) => map.sample(EnvironmentMap::direction_to_uv(direction)),
+        }
+    }
+
+    /// Cosine-weighted average radiance over the hemisphere around `normal`,
+    /// i.e. the radiance a white surface facing `normal` reflects
+    pub fn ambient(&self, normal: Vec3) -> Vec3 {
+        let tangent = normal.any_orthonormal_vector();
+        let bitangent = normal.cross(tangent);
+        let n_samples = Self::AMBIENT_SAMPLES;
+        let mut sum = Vec3::ZERO;
+
+        // stratified cosine-weighted directions
+        for j in 0..n_samples {
+            for i in 0..n_samples {
+                let phi = 2.0 * PI * (i as f32 + 0.5) / n_samples as f32;
+                let sin_theta_squared = (j as f32 + 0.5) / n_samples as f32;
+                let sin_theta = sin_theta_squared.sqrt();
+
+                let direction = sin_theta * phi.cos() * tangent
+                    + sin_theta * phi.sin() * bitangent
+                    + (1.0 - sin_theta_squared).sqrt() * normal;
+
+                sum += self.radiance(direction.normalize());
+            }
+        }
+
+        sum / (n_samples * n_samples) as f32
+    }
+
+    pub fn to_uniform(&self) -> EnvironmentUniform {
+        let (kind, zenith, horizon, ground, intensity) = match self {
+            Self::Uniform(color) => (Self::UNIFORM, *color, *color, *color, 1.0),
+            Self::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => (Self::GRADIENT, *zenith, *horizon, *ground, 1.0),
+            Self::Map(map) => (Self::MAP, Vec3::ZERO, Vec3::ZERO, Vec3::ZERO, map.intensity),
+        };
+
+        // voxel faces only ever face along the axes
+        let ambient = [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ]
+        .map(|normal| self.ambient(normal).extend(0.0));
+
+        EnvironmentUniform {
+            zenith,
+            kind,
+            horizon,
+            intensity,
+            ground,
+            _padding: 0,
+            ambient,
+        }
+    }
+}
+
+/// Equirectangular environment map, `u` goes around the Y axis and `v` from +Y down to -Y
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentMap {
+    pub size: UVec2,
+    /// Rows from top to bottom in [`wgpu::TextureFormat::Rgb9e5Ufloat`] layout
+    pub texels: Vec<u32>,
+    /// Multiplies all texels
+    pub intensity: f32,
+}
+
+impl EnvironmentMap {
+    /// Loads a Radiance `.hdr` image
+    pub fn load(path: impl AsRef<Path>, intensity: f32) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgb32f();
+
+        Ok(Self {
+            size: UVec2::new(image.width(), image.height()),
+            texels: image
+                .pixels()
+                .map(|pixel| pack_rgb9e5(Vec3::from_array(pixel.0)))
+                .collect(),
+            intensity,
+        })
+    }
+
+    /// Box filters the map by the smallest integer factor fitting both sides into `max_size`,
+    /// `None` if it fits already
+    pub fn downsampled(&self, max_size: u32) -> Option<Self> {
+        let factor = self.size.max_element().div_ceil(max_size);
+
+        if factor <= 1 {
+            return None;
+        }
+
+        let size = UVec2::new(self.size.x.div_ceil(factor), self.size.y.div_ceil(factor));
+
+        let texels = (0..size.y)
+            .flat_map(|y| (0..size.x).map(move |x| UVec2::new(x, y)))
+            .map(|pos| {
+                let lo = factor * pos;
+                let hi = (lo + factor).min(self.size);
+
+                let sum = (lo.y..hi.y)
+                    .flat_map(|y| (lo.x..hi.x).map(move |x| UVec2::new(x, y)))
+                    .map(|pos| self.texel(pos))
+                    .sum::<Vec3>();
+
+                pack_rgb9e5(sum / (hi - lo).element_product() as f32)
+            })
+            .collect();
+
+        Some(Self {
+            size,
+            texels,
+            intensity: self.intensity,
+        })
+    }
+
+    pub fn direction_to_uv(direction: Vec3) -> Vec2 {
+        Vec2::new(
+            direction.z.atan2(direction.x) / (2.0 * PI) + 0.5,
+            direction.y.clamp(-1.0, 1.0).acos() / PI,
+        )
+    }
+
+    pub fn texel(&self, pos: UVec2) -> Vec3 {
+        unpack_rgb9e5(self.texels[(pos.y * self.size.x + pos.x) as usize])
+    }
+
+    /// Bilinear sample wrapping around horizontally and clamped vertically
+    /// as the compute shader's sampler does
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let size = self.size.as_ivec2();
+        let coord = uv * self.size.as_vec2() - 0.5;
+        let lo = coord.floor().as_ivec2();
+        let fraction = coord - coord.floor();
+
+        let texel = |x: i32, y: i32| {
+            self.texel(UVec2::new(
+                x.rem_euclid(size.x) as u32,
+                y.clamp(0, size.y - 1) as u32,
+            ))
+        };
+
+        let top = texel(lo.x, lo.y).lerp(texel(lo.x + 1, lo.y), fraction.x);
+        let bottom = texel(lo.x, lo.y + 1).lerp(texel(lo.x + 1, lo.y + 1), fraction.x);
+
+        self.intensity * top.lerp(bottom, fraction.y)
+    }
+}
+
+const RGB9E5_MANTISSA_BITS: i32 = 9;
+const RGB9E5_EXPONENT_BIAS: i32 = 15;
+const RGB9E5_MAX_EXPONENT: i32 = 31;
+
+/// Packs a color with a shared 5-bit exponent and 9-bit mantissas, see
+/// <https://registry.khronos.org/OpenGL/extensions/EXT/EXT_texture_shared_exponent.txt>
+pub fn pack_rgb9e5(color: Vec3) -> u32 {
+    let max_mantissa = ((1 << RGB9E5_MANTISSA_BITS) - 1) as f32;
+    let max_value = max_mantissa / (1 << RGB9E5_MANTISSA_BITS) as f32
+        * 2.0_f32.powi(RGB9E5_MAX_EXPONENT - RGB9E5_EXPONENT_BIAS);
+
+    // also maps NaN to zero
+    let color = color.max(Vec3::ZERO).min(Vec3::splat(max_value));
+    let max_component = color.max_element();
+
+    let mut exponent = (max_component.log2().floor() as i32).max(-RGB9E5_EXPONENT_BIAS - 1)
+        + 1
+        + RGB9E5_EXPONENT_BIAS;
+
+    let scale =
+        |exponent: i32| 2.0_f32.powi(exponent - RGB9E5_EXPONENT_BIAS - RGB9E5_MANTISSA_BITS);
+
+    if (max_component / scale(exponent) + 0.5).floor() > max_mantissa {
+        exponent += 1;
+    }
+
+    let [r, g, b] = (color / scale(exponent) + 0.5)
+        .floor()
+        .as_uvec3()
+        .to_array();
+
+    r | g << 9 | b << 18 | (exponent as u32) << 27
+}
+
+pub fn unpack_rgb9e5(packed: u32) -> Vec3 {
+    let mantissa = UVec3::new(packed, packed >> 9, packed >> 18) & UVec3::splat(0x1FF);
+    let exponent = (packed >> 27) as i32;
+
+    mantissa.as_vec3() * 2.0_f32.powi(exponent - RGB9E5_EXPONENT_BIAS - RGB9E5_MANTISSA_BITS)
+}
+
+/// [`Environment`] layout as the compute shader reads it (std140)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct EnvironmentUniform {
+    /// Color of uniform environments
+    pub zenith: Vec3,
+    pub kind: u32,
+    pub horizon: Vec3,
+    /// Multiplies the environment map
+    pub intensity: f32,
+    pub ground: Vec3,
+    pub _padding: u32,
+    /// [`Environment::ambient`] around +X, -X, +Y, -Y, +Z and -Z
+    pub ambient: [Vec4; 6],
+}
@@ -0,0 +1,107 @@
+use glam::*;
+use std::collections::HashSet;
+use winit::event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+/// Keyboard and mouse state accumulated from [`WindowEvent`]s between frames
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Input {
+    pub pressed_keys: HashSet<KeyCode>,
+    pub just_pressed_keys: HashSet<KeyCode>,
+    pub pressed_buttons: HashSet<MouseButton>,
+    pub cursor_position: Option<Vec2>,
+    /// Cursor movement in pixels since the last frame
+    pub cursor_delta: Vec2,
+    /// Wheel movement in lines since the last frame, positive is away from the user
+    pub scroll_delta: f32,
+}
+
+impl Input {
+    /// Approximate height of a scroll line for touchpads reporting pixel deltas
+    pub const PIXELS_PER_LINE: f32 = 20.0;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(code),
+                        state,
+                        repeat,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    if !repeat {
+                        self.just_pressed_keys.insert(*code);
+                    }
+
+                    self.pressed_keys.insert(*code);
+                }
+                ElementState::Released => {
+                    self.pressed_keys.remove(code);
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.pressed_buttons.insert(*button);
+                }
+                ElementState::Released => {
+                    self.pressed_buttons.remove(button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = Vec2::new(position.x as f32, position.y as f32);
+
+                if let Some(prev_position) = self.cursor_position {
+                    self.cursor_delta += position - prev_position;
+                }
+
+                self.cursor_position = Some(position);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(pixels) => pixels.y as f32 / Self::PIXELS_PER_LINE,
+                };
+            }
+            WindowEvent::Focused(false) => {
+                self.pressed_keys.clear();
+                self.pressed_buttons.clear();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    pub fn is_key_just_pressed(&self, key: KeyCode) -> bool {
+        self.just_pressed_keys.contains(&key)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.pressed_buttons.contains(&button)
+    }
+
+    /// `1.0` if only `positive` is pressed, `-1.0` if only `negative` is pressed and `0.0` otherwise
+    pub fn axis(&self, negative: KeyCode, positive: KeyCode) -> f32 {
+        self.is_key_pressed(positive) as u8 as f32 - self.is_key_pressed(negative) as u8 as f32
+    }
+
+    /// Resets per-frame deltas, call it after all systems have read the input
+    pub fn end_frame(&mut self) {
+        self.just_pressed_keys.clear();
+        self.cursor_delta = Vec2::ZERO;
+        self.scroll_delta = 0.0;
+    }
+}
@@ -0,0 +1,116 @@
+use bytemuck::{Pod, Zeroable};
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+/// Lights have no falloff, `color` is not limited to `[0, 1]` and doubles as intensity
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum Light {
+    Point {
+        position: Vec3,
+        #[serde(default = "Light::default_color")]
+        color: Vec3,
+    },
+    /// Infinitely far light shining along `direction`
+    Directional {
+        direction: Vec3,
+        #[serde(default = "Light::default_color")]
+        color: Vec3,
+    },
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self::Point {
+            position: Vec3::new(10.0, 12.0, 16.0),
+            color: Self::default_color(),
+        }
+    }
+}
+
+impl Light {
+    fn default_color() -> Vec3 {
+        Vec3::ONE
+    }
+
+    pub fn color(&self) -> Vec3 {
+        match *self {
+            Self::Point { color, .. } | Self::Directional { color, .. } => color,
+        }
+    }
+
+    /// Direction from `position` towards the light and distance to it
+    pub fn direction_from(&self, position: Vec3) -> (Vec3, f32) {
+        match *self {
+            Self::Point {
+                position: light_position,
+                ..
+            } => {
+                let offset = light_position - position;
+                let distance = offset.length();
+
+                (offset / distance, distance)
+            }
+            Self::Directional { direction, .. } => (-direction.normalize(), f32::INFINITY),
+        }
+    }
+
+    pub fn to_uniform(&self) -> LightUniform {
+        let (kind, position) = match *self {
+            Self::Point { position, .. } => (LightUniform::POINT, position),
+            Self::Directional { direction, .. } => {
+                (LightUniform::DIRECTIONAL, direction.normalize())
+            }
+        };
+
+        LightUniform {
+            position,
+            kind,
+            color: self.color(),
+            _padding: 0,
+        }
+    }
+
+    /// Light buffer contents as the compute shader reads it (std430):
+    /// a 16-byte header with the light count followed by the lights,
+    /// always has room for at least one light so the binding is never empty
+    pub fn buffer_contents(lights: &[Self]) -> Vec<u8> {
+        let header = LightBufferHeader {
+            light_count: lights.len() as u32,
+            _padding: [0; 3],
+        };
+
+        let mut uniforms = lights.iter().map(Self::to_uniform).collect::<Vec<_>>();
+
+        if uniforms.is_empty() {
+            uniforms.push(LightUniform::default());
+        }
+
+        let mut contents = bytemuck::bytes_of(&header).to_vec();
+        contents.extend_from_slice(bytemuck::cast_slice(&uniforms));
+        contents
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct LightBufferHeader {
+    pub light_count: u32,
+    pub _padding: [u32; 3],
+}
+
+/// [`Light`] layout as the compute shader reads it (std430)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct LightUniform {
+    /// Position of point lights or normalized direction of directional lights
+    pub position: Vec3,
+    pub kind: u32,
+    pub color: Vec3,
+    pub _padding: u32,
+}
+
+impl LightUniform {
+    pub const POINT: u32 = 0;
+    pub const DIRECTIONAL: u32 = 1;
+}
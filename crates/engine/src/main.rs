@@ -1,33 +1,170 @@
 #![allow(dead_code)]
 
+pub mod camera;
+pub mod camera_controller;
+pub mod capture;
 pub mod context;
+pub mod dynamic_resolution;
+pub mod environment;
+pub mod input;
+pub mod light;
+pub mod material;
 pub mod octree;
+pub mod pass_constants;
+pub mod profiler;
+pub mod raytrace;
+pub mod scene;
+pub mod shading;
+pub mod tonemapping;
+pub mod triangle_demo;
 pub mod util;
+pub mod vox;
 pub mod voxels_demo;
+pub mod world;
 
-use context::RenderContext;
+use camera_controller::{CameraController, OrbitController};
+use capture::{Recording, SaveImageError};
+use context::{ContextOptions, RenderContext, RenderContextError};
+use dynamic_resolution::DynamicResolution;
+use error_stack::Report;
+use glam::*;
+use input::Input;
+use raytrace::Shading;
+use scene::Scene;
+use shading::{DebugView, RenderMode};
 use std::error::Error;
+use std::f32::consts::TAU;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Instant;
-use tracing::{debug, error};
+use thiserror::Error;
+use tracing::{debug, error, warn};
+use voxels_demo::{DrawError, VoxelsDemo};
 use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalSize;
 use winit::event::WindowEvent;
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::keyboard::KeyCode;
 use winit::window::{Window, WindowAttributes, WindowId};
 
-#[derive(Default)]
 struct App {
+    scene: Scene,
+    context_options: ContextOptions,
     window: Option<Arc<Window>>,
     render_context: Option<RenderContext>,
     now: Option<Instant>,
     voxels_demo: Option<crate::voxels_demo::VoxelsDemo>,
+    input: Input,
+    camera_controller: CameraController,
+    dynamic_resolution: DynamicResolution,
+    /// Dynamic resolution was enabled before recording and is enabled again once it stops
+    resume_dynamic_resolution: bool,
+    /// Orbit camera azimuth at the start of the recording
+    turntable_azimuth: f32,
+    /// Render context creation failure that stopped the event loop, returned from `main`
+    error: Option<Report<RenderContextError>>,
+}
+
+impl App {
+    /// Render scale change per `-`/`=` key press, pressing either disables dynamic resolution
+    const RENDER_SCALE_STEP: f32 = 0.25;
+    const DYNAMIC_RESOLUTION_KEY: KeyCode = KeyCode::F2;
+    const RENDER_MODE_KEY: KeyCode = KeyCode::KeyP;
+    const SHADOWS_KEY: KeyCode = KeyCode::F3;
+    const AMBIENT_OCCLUSION_KEY: KeyCode = KeyCode::F4;
+    const REFRACTION_KEY: KeyCode = KeyCode::F5;
+    const TONEMAPPING_KEY: KeyCode = KeyCode::KeyT;
+    const DEBUG_VIEW_KEY: KeyCode = KeyCode::F6;
+    const PROFILING_KEY: KeyCode = KeyCode::F7;
+    const RECORDING_KEY: KeyCode = KeyCode::F9;
+    const SCREENSHOT_KEY: KeyCode = KeyCode::F12;
+    /// Screenshots and recordings are saved here relative to the working directory
+    const CAPTURE_DIR: &'static str = "captures";
+    /// Exposure change in stops per `[`/`]` key press
+    const EXPOSURE_STEP: f32 = 0.5;
+
+    fn new(scene: Scene, context_options: ContextOptions) -> Self {
+        let camera_controller = CameraController::Orbit(OrbitController::from_camera(
+            &scene.camera,
+            scene.camera_target,
+        ));
+
+        Self {
+            window: None,
+            render_context: None,
+            now: None,
+            voxels_demo: None,
+            input: Input::new(),
+            camera_controller,
+            dynamic_resolution: scene.dynamic_resolution,
+            resume_dynamic_resolution: false,
+            turntable_azimuth: 0.0,
+            error: None,
+            scene,
+            context_options,
+        }
+    }
+
+    /// Presents without vsync while dynamic resolution is enabled
+    /// for the frame times to reflect the rendering cost
+    fn update_present_mode(&self) {
+        let (Some(context), Some(window)) = (self.render_context.as_ref(), self.window.as_ref())
+        else {
+            return;
+        };
+
+        let window_size = window.inner_size();
+
+        // minimized windows are reconfigured once they are resized back
+        if window_size.width == 0 || window_size.height == 0 {
+            return;
+        }
+
+        let present_mode = match self.dynamic_resolution.enabled {
+            true => wgpu::PresentMode::AutoNoVsync,
+            false => wgpu::PresentMode::AutoVsync,
+        };
+
+        if let Err(err) = context.set_present_mode(present_mode, window_size) {
+            error!(?err, "failed to change the present mode");
+        }
+    }
+
+    /// Re-creates the render context and all GPU resources after the device has been lost
+    fn recover_device(&mut self, event_loop: &ActiveEventLoop) {
+        let (Some(window), Some(voxels_demo)) = (self.window.as_ref(), self.voxels_demo.as_mut())
+        else {
+            return;
+        };
+
+        warn!("recreating the render context after the device loss");
+
+        // the window can only have one surface at a time
+        self.render_context = None;
+        voxels_demo.context.surface = None;
+
+        let render_context = match RenderContext::new(window, &self.context_options) {
+            Ok(context) => context,
+            Err(report) => {
+                error!("failed to recreate the render context");
+                self.error = Some(report);
+                event_loop.exit();
+                return;
+            }
+        };
+
+        voxels_demo.recreate(render_context.clone(), &self.scene);
+        self.render_context = Some(render_context);
+    }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let window_size = PhysicalSize::new(self.scene.window_size.x, self.scene.window_size.y);
+
         let window = Arc::new(
             event_loop
-                .create_window(WindowAttributes::default())
+                .create_window(WindowAttributes::default().with_inner_size(window_size))
                 .unwrap(),
         );
 
@@ -35,15 +172,32 @@ impl ApplicationHandler for App {
 
         debug!(size = ?window_size, "window created");
 
-        let render_context = RenderContext::new(&window);
+        let render_context = match RenderContext::new(&window, &self.context_options) {
+            Ok(context) => context,
+            Err(report) => {
+                error!(
+                    "available adapters:\n{}",
+                    RenderContext::diagnose_adapters()
+                );
+                self.error = Some(report);
+                event_loop.exit();
+                return;
+            }
+        };
+
+        let mut voxels_demo = VoxelsDemo::new(render_context.clone(), &self.scene);
+        voxels_demo.resize(UVec2::new(window_size.width, window_size.height));
+        voxels_demo.set_profiling(self.scene.gpu_profiling);
 
-        self.voxels_demo.replace(voxels_demo::VoxelsDemo::new(render_context.clone()));
+        self.voxels_demo.replace(voxels_demo);
         self.render_context.replace(render_context);
         self.window.replace(window);
         self.now.replace(Instant::now());
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        self.input.handle_event(&event);
+
         match event {
             WindowEvent::CloseRequested => {
                 debug!("closing window and exiting event loop");
@@ -56,9 +210,7 @@ impl ApplicationHandler for App {
                 // this event rather than in AboutToWait, since rendering in here allows
                 // the program to gracefully handle redraws requested by the OS.
 
-                if let Some(voxels_demo) = self.voxels_demo.as_ref() {
-                    voxels_demo.draw();
-                }
+                let mut time_step = 0.0;
 
                 if let Some(prev_instant) = self.now.as_mut() {
                     let now = Instant::now();
@@ -67,9 +219,187 @@ impl ApplicationHandler for App {
 
                     debug!(?duration, fps);
 
+                    time_step = duration.as_secs_f32();
                     *prev_instant = now;
                 }
 
+                if let Some(voxels_demo) = self.voxels_demo.as_mut() {
+                    let scale_change = Self::RENDER_SCALE_STEP
+                        * (self.input.is_key_just_pressed(KeyCode::Equal) as i32
+                            - self.input.is_key_just_pressed(KeyCode::Minus) as i32)
+                            as f32;
+
+                    if scale_change != 0.0 {
+                        self.dynamic_resolution.set_enabled(false);
+                        voxels_demo.set_render_scale(voxels_demo.render_scale + scale_change);
+                        debug!(
+                            render_scale = voxels_demo.render_scale,
+                            "render scale changed"
+                        );
+                    }
+
+                    if self.input.is_key_just_pressed(Self::DYNAMIC_RESOLUTION_KEY) {
+                        let enabled = !self.dynamic_resolution.enabled;
+                        self.dynamic_resolution.set_enabled(enabled);
+                        debug!(enabled, "dynamic resolution toggled");
+                    }
+
+                    if self.input.is_key_just_pressed(Self::RENDER_MODE_KEY) {
+                        let render_mode = match voxels_demo.render_mode {
+                            RenderMode::Raytrace => RenderMode::PathTrace,
+                            RenderMode::PathTrace => RenderMode::Raytrace,
+                        };
+
+                        voxels_demo.set_render_mode(render_mode);
+                        debug!(?render_mode, "render mode changed");
+                    }
+
+                    if self.input.is_key_just_pressed(Self::SHADOWS_KEY) {
+                        voxels_demo.set_shadows(!voxels_demo.shadows);
+                        debug!(shadows = voxels_demo.shadows, "shadows toggled");
+                    }
+
+                    if self.input.is_key_just_pressed(Self::REFRACTION_KEY) {
+                        voxels_demo.set_refraction(!voxels_demo.refraction);
+                        debug!(refraction = voxels_demo.refraction, "refraction toggled");
+                    }
+
+                    if self.input.is_key_just_pressed(Self::AMBIENT_OCCLUSION_KEY) {
+                        voxels_demo.set_ambient_occlusion(voxels_demo.ambient_occlusion.next());
+                        debug!(
+                            ambient_occlusion = ?voxels_demo.ambient_occlusion,
+                            "ambient occlusion changed"
+                        );
+                    }
+
+                    if self.input.is_key_just_pressed(Self::DEBUG_VIEW_KEY) {
+                        voxels_demo.set_debug_view(voxels_demo.debug_view.next());
+                        debug!(debug_view = ?voxels_demo.debug_view, "debug view changed");
+                    }
+
+                    if self.input.is_key_just_pressed(Self::PROFILING_KEY) {
+                        voxels_demo.set_profiling(voxels_demo.profiler.is_none());
+                        debug!(
+                            profiling = voxels_demo.profiler.is_some(),
+                            "gpu profiling toggled"
+                        );
+                    }
+
+                    if self.input.is_key_just_pressed(Self::TONEMAPPING_KEY) {
+                        let screen_quad = &mut voxels_demo.screen_quad;
+                        screen_quad.tonemapping = screen_quad.tonemapping.next();
+                        debug!(tonemapping = ?screen_quad.tonemapping, "tonemapping changed");
+                    }
+
+                    let exposure_change = Self::EXPOSURE_STEP
+                        * (self.input.is_key_just_pressed(KeyCode::BracketRight) as i32
+                            - self.input.is_key_just_pressed(KeyCode::BracketLeft) as i32)
+                            as f32;
+
+                    if exposure_change != 0.0 {
+                        voxels_demo.screen_quad.exposure += exposure_change;
+                        debug!(
+                            exposure = voxels_demo.screen_quad.exposure,
+                            "exposure changed"
+                        );
+                    }
+
+                    if self.input.is_key_just_pressed(Self::SCREENSHOT_KEY) {
+                        let path = Path::new(Self::CAPTURE_DIR)
+                            .join(capture::timestamped_name("screenshot") + ".png");
+
+                        let result = std::fs::create_dir_all(Self::CAPTURE_DIR)
+                            .map_err(SaveImageError::from)
+                            .and_then(|()| voxels_demo.save_screenshot(&path));
+
+                        match result {
+                            Ok(()) => debug!(?path, "screenshot saved"),
+                            Err(err) => error!(?path, %err, "failed to save a screenshot"),
+                        }
+                    }
+
+                    if self.input.is_key_just_pressed(Self::RECORDING_KEY) {
+                        match voxels_demo.recording.take() {
+                            Some(recording) => debug!(
+                                dir = ?recording.dir,
+                                n_frames = recording.n_frames,
+                                "recording stopped"
+                            ),
+                            None => {
+                                let dir = Path::new(Self::CAPTURE_DIR)
+                                    .join(capture::timestamped_name("recording"));
+
+                                match Recording::new(&dir, self.scene.recording_fps) {
+                                    Ok(recording) => {
+                                        // all recorded frames have to be of the same size
+                                        self.resume_dynamic_resolution =
+                                            self.dynamic_resolution.enabled;
+                                        self.dynamic_resolution.set_enabled(false);
+
+                                        if let CameraController::Orbit(orbit) =
+                                            &self.camera_controller
+                                        {
+                                            self.turntable_azimuth = orbit.azimuth;
+                                        }
+
+                                        voxels_demo.recording = Some(recording);
+                                        debug!(?dir, "recording started");
+                                    }
+                                    Err(err) => error!(?dir, %err, "failed to start recording"),
+                                }
+                            }
+                        }
+                    }
+
+                    // recording also stops if a frame fails to save
+                    if self.resume_dynamic_resolution && voxels_demo.recording.is_none() {
+                        self.resume_dynamic_resolution = false;
+                        self.dynamic_resolution.set_enabled(true);
+                        debug!("dynamic resolution resumed after recording");
+                    }
+
+                    // recorded frames advance by the same time step however long they take
+                    if let Some(recording) = voxels_demo.recording.as_ref() {
+                        time_step = recording.time_step;
+                    }
+
+                    if let Some(render_scale) = self
+                        .dynamic_resolution
+                        .update(time_step, voxels_demo.render_scale)
+                    {
+                        voxels_demo.set_render_scale(render_scale);
+                        debug!(render_scale, "dynamic resolution changed render scale");
+                    }
+
+                    let turntable_period = self.scene.turntable_period;
+
+                    match (&mut self.camera_controller, voxels_demo.recording.is_some()) {
+                        // recorded turntables ignore the input to be reproducible
+                        (CameraController::Orbit(orbit), true) if turntable_period > 0.0 => {
+                            orbit.azimuth = self.turntable_azimuth
+                                + TAU * voxels_demo.time() / turntable_period;
+                            orbit.apply(&mut voxels_demo.camera);
+                        }
+                        (controller, _) => {
+                            controller.update(&mut voxels_demo.camera, &self.input, time_step)
+                        }
+                    }
+
+                    let window_size = self.window.as_ref().unwrap().inner_size();
+
+                    match voxels_demo.draw(UVec2::new(window_size.width, window_size.height)) {
+                        Ok(()) => {}
+                        Err(DrawError::DeviceLost) => self.recover_device(event_loop),
+                        Err(err @ DrawError::OutOfMemory) => {
+                            error!(%err, "exiting");
+                            event_loop.exit();
+                        }
+                    }
+                }
+
+                self.input.end_frame();
+                self.update_present_mode();
+
                 // Queue a RedrawRequested event.
                 //
                 // You only need to call this if you've determined that you need to redraw in
@@ -82,27 +412,167 @@ impl ApplicationHandler for App {
                     break 'event;
                 };
 
+                // minimized windows have zero size and nothing to render to
+                if size.width == 0 || size.height == 0 {
+                    break 'event;
+                }
+
                 if let Err(err) = context.resize(size) {
                     error!(?err);
                     break 'event;
                 }
+
+                if let Some(voxels_demo) = self.voxels_demo.as_mut() {
+                    voxels_demo.resize(UVec2::new(size.width, size.height));
+                }
             }
             _ => (),
         }
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let event_loop = EventLoop::new().unwrap();
+/// Renders a single frame without opening any window and saves it to `output`
+fn render_headless(
+    scene: &Scene,
+    context_options: &ContextOptions,
+    output: &str,
+) -> Result<(), Box<dyn Error>> {
+    let render_context = match RenderContext::new_headless(context_options) {
+        Ok(context) => context,
+        Err(report) => {
+            warn!("{report:?}");
+            warn!("no suitable adapter available, falling back to the CPU raytracer");
+            return render_cpu(scene, output);
+        }
+    };
+
+    let mut voxels_demo = VoxelsDemo::new(render_context, scene);
+    voxels_demo.set_profiling(scene.gpu_profiling);
+
+    let n_samples = match scene.render_mode {
+        RenderMode::Raytrace => 1,
+        RenderMode::PathTrace => scene.samples,
+    };
 
+    for _ in 0..n_samples {
+        voxels_demo.render_offscreen(0.0);
+    }
+    voxels_demo.read_frame()?.save(output)?;
+
+    // reading the frame back has waited for all the timestamps
+    if let Some(profiler) = voxels_demo.profiler.as_mut() {
+        profiler.poll(&voxels_demo.context.device);
+        profiler.log_stats();
+    }
+
+    debug!(output, "frame saved");
+
+    Ok(())
+}
+
+fn render_cpu(scene: &Scene, output: &str) -> Result<(), Box<dyn Error>> {
+    if RenderMode::PathTrace == scene.render_mode && DebugView::Off == scene.debug_view {
+        warn!("path tracing is not available on the CPU, rendering direct lighting instead");
+    }
+
+    raytrace::render_image(
+        &scene.world.flatten(),
+        &scene.camera,
+        &Shading {
+            lights: &scene.lights,
+            materials: &scene.materials,
+            shadows: scene.shadows,
+            refraction: scene.refraction,
+            ambient_occlusion: scene.ambient_occlusion,
+            debug_view: scene.debug_view,
+            environment: &scene.environment,
+            ambient: scene.environment.to_uniform().ambient,
+        },
+        scene.tonemapping,
+        scene.exposure,
+        VoxelsDemo::scaled_size(scene.window_size, scene.render_scale),
+    )
+    .save(output)?;
+
+    debug!(output, "frame saved");
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ArgsError {
+    #[error("`--backends` expects a comma separated list, e.g. `vulkan,gl`")]
+    MissingBackends,
+    #[error("no known backends in `{0}`")]
+    UnknownBackends(String),
+}
+
+/// Takes `--backends <list>` and `--no-push-constants` out of `args`,
+/// the backends default to the ones in `WGPU_BACKEND`
+fn take_context_options(args: &mut Vec<&str>) -> Result<ContextOptions, ArgsError> {
+    let mut options = ContextOptions::from_env();
+
+    if let Some(index) = args.iter().position(|&arg| "--backends" == arg) {
+        let &list = args.get(index + 1).ok_or(ArgsError::MissingBackends)?;
+
+        options.backends = wgpu::util::parse_backends_from_comma_list(list);
+
+        if options.backends.is_empty() {
+            return Err(ArgsError::UnknownBackends(list.to_owned()));
+        }
+
+        args.drain(index..=index + 1);
+    }
+
+    if let Some(index) = args.iter().position(|&arg| "--no-push-constants" == arg) {
+        options.disable_push_constants = true;
+        args.remove(index);
+    }
+
+    Ok(options)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(tracing::Level::DEBUG)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let context_options = take_context_options(&mut args)?;
+
+    let (scene_path, args) = match args.as_slice() {
+        [_, "--scene", path, rest @ ..] => (Some(*path), rest),
+        [_, rest @ ..] => (None, rest),
+        [] => (None, &[][..]),
+    };
+
+    let scene = match scene_path.map_or_else(Scene::load_default, Scene::load) {
+        Ok(scene) => scene,
+        Err(err) => return Err(Report::new(err).into()),
+    };
+
+    debug!(
+        path = scene_path.unwrap_or(Scene::DEFAULT_NAME),
+        "scene loaded"
+    );
+
+    match args {
+        ["--headless", output] => return render_headless(&scene, &context_options, output),
+        ["--headless", "--cpu", output] => return render_cpu(&scene, output),
+        _ => {}
+    }
+
+    let event_loop = EventLoop::new().unwrap();
+
     event_loop.set_control_flow(ControlFlow::Poll);
-    event_loop.run_app(&mut App::default())?;
+    let mut app = App::new(scene, context_options);
+    event_loop.run_app(&mut app)?;
 
-    Ok(())
+    match app.error {
+        Some(report) => Err(report.into()),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,105 @@
+use bytemuck::{Pod, Zeroable};
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+/// Surface properties shared by all voxels with the same material index
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Material {
+    /// Multiplies the voxel color
+    pub albedo: Vec3,
+    /// Emitted radiance, not limited to `[0, 1]`
+    pub emission: Vec3,
+    pub roughness: f32,
+    pub metalness: f32,
+    /// Index of refraction
+    pub ior: f32,
+    pub opacity: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            albedo: Vec3::ONE,
+            emission: Vec3::ZERO,
+            roughness: 1.0,
+            metalness: 0.0,
+            ior: 1.5,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl Material {
+    /// Voxels address materials with a `u8` index
+    pub const MAX_COUNT: usize = 256;
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [("albedo", self.albedo), ("emission", self.emission)] {
+            if !(value.cmpge(Vec3::ZERO).all() && value.is_finite()) {
+                return Err(format!(
+                    "material {name} is {value}, expected to be finite and non-negative"
+                ));
+            }
+        }
+
+        for (name, value) in [
+            ("roughness", self.roughness),
+            ("metalness", self.metalness),
+            ("opacity", self.opacity),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!(
+                    "material {name} is {value}, expected to be in [0, 1]"
+                ));
+            }
+        }
+
+        if !(self.ior >= 1.0 && self.ior.is_finite()) {
+            return Err(format!(
+                "material index of refraction is {}, expected to be finite and at least 1",
+                self.ior
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn to_uniform(&self) -> MaterialUniform {
+        MaterialUniform {
+            albedo: self.albedo,
+            roughness: self.roughness,
+            emission: self.emission,
+            metalness: self.metalness,
+            ior: self.ior,
+            opacity: self.opacity,
+            _padding: [0; 2],
+        }
+    }
+
+    /// Material table as the compute shader reads it (std430),
+    /// always has [`Material::MAX_COUNT`] entries with missing ones set to the default material
+    pub fn buffer_contents(materials: &[Self]) -> Vec<MaterialUniform> {
+        let mut uniforms = materials
+            .iter()
+            .take(Self::MAX_COUNT)
+            .map(Self::to_uniform)
+            .collect::<Vec<_>>();
+
+        uniforms.resize(Self::MAX_COUNT, Self::default().to_uniform());
+        uniforms
+    }
+}
+
+/// [`Material`] layout as the compute shader reads it (std430)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct MaterialUniform {
+    pub albedo: Vec3,
+    pub roughness: f32,
+    pub emission: Vec3,
+    pub metalness: f32,
+    pub ior: f32,
+    pub opacity: f32,
+    pub _padding: [u32; 2],
+}
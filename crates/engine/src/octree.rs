@@ -1,7 +1,10 @@
+use crate::tonemapping::srgb_to_linear;
 use bytemuck::{Pod, Zeroable};
 use glam::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
+/// RGBA8 color with sRGB encoded channels and linear alpha, as in `.vox` palettes
 #[repr(transparent)]
 #[derive(
     Clone,
@@ -51,6 +54,17 @@ impl Color {
     pub const fn from_vec3(color: Vec3) -> Self {
         Self::rgb(color.x, color.y, color.z)
     }
+
+    pub fn to_vec4(self) -> Vec4 {
+        let [r, g, b, a] = self.0.to_le_bytes();
+        Vec4::new(r as f32, g as f32, b as f32, a as f32) / 255.0
+    }
+
+    /// Decodes the sRGB encoded channels keeping the alpha as is
+    pub fn to_linear(self) -> Vec4 {
+        let color = self.to_vec4();
+        srgb_to_linear(color.xyz()).extend(color.w)
+    }
 }
 
 impl From<Vec3> for Color {
@@ -59,14 +73,42 @@ impl From<Vec3> for Color {
     }
 }
 
+/// Voxel colored by `color` and shaded by the material at index `material` of the material table,
+/// voxels with [`Color::TRANSPARENT_BLACK`] are empty
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Voxel {
+    pub color: Color,
+    pub material: u8,
+}
+
+impl Voxel {
+    pub const EMPTY: Self = Self::new(Color::TRANSPARENT_BLACK, 0);
+
+    pub const fn new(color: Color, material: u8) -> Self {
+        Self { color, material }
+    }
+
+    pub const fn is_empty(self) -> bool {
+        Color::TRANSPARENT_BLACK.0 == self.color.0
+    }
+}
+
+impl From<Color> for Voxel {
+    fn from(color: Color) -> Self {
+        Self::new(color, 0)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Chunk {
-    pub colors: [Color; Chunk::VOLUME],
+    pub voxels: [Voxel; Chunk::VOLUME],
 }
 
 impl Default for Chunk {
     fn default() -> Self {
-        Self::all_same(Color::BLACK)
+        Self::all_same(Voxel::new(Color::BLACK, 0))
     }
 }
 
@@ -74,15 +116,27 @@ impl Chunk {
     pub const SIZE: usize = 16;
     pub const VOLUME: usize = Self::SIZE * Self::SIZE * Self::SIZE;
 
-    pub const fn all_same(color: Color) -> Self {
+    pub const fn all_same(voxel: Voxel) -> Self {
         Self {
-            colors: [color; Self::VOLUME],
+            voxels: [voxel; Self::VOLUME],
         }
     }
 
+    pub const fn index(pos: UVec3) -> usize {
+        Self::SIZE * (Self::SIZE * pos.z as usize + pos.y as usize) + pos.x as usize
+    }
+
+    pub fn get(&self, pos: UVec3) -> Voxel {
+        self.voxels[Self::index(pos)]
+    }
+
+    pub fn set(&mut self, pos: UVec3, voxel: Voxel) {
+        self.voxels[Self::index(pos)] = voxel;
+    }
+
     pub fn new_sphere() -> Self {
         Self {
-            colors: std::array::from_fn(|index| {
+            voxels: std::array::from_fn(|index| {
                 let x = index % Self::SIZE;
                 let yz = index / Self::SIZE;
                 let y = yz % Self::SIZE;
@@ -91,11 +145,467 @@ impl Chunk {
                 let center = 0.5 * Vec3::splat(Self::SIZE as f32);
 
                 if Vec3::new(x as f32, y as f32, z as f32).distance(center) < 5.0 {
-                    Color::rgb8(20 * x as u8, 20 * y as u8, 20 * z as u8)
+                    Color::rgb8(20 * x as u8, 20 * y as u8, 20 * z as u8).into()
                 } else {
-                    Color::TRANSPARENT_BLACK
+                    Voxel::EMPTY
                 }
             }),
         }
     }
 }
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Leaf(Voxel),
+    Branch(Box<[Node; 8]>),
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Node {
+    pub const EMPTY: Self = Self::Leaf(Voxel::EMPTY);
+
+    /// Index of the child containing `pos` for a node spanning `2^level` voxels
+    pub const fn child_index(pos: UVec3, level: u32) -> usize {
+        let shift = level - 1;
+
+        (((pos.x >> shift) & 1) | ((pos.y >> shift) & 1) << 1 | ((pos.z >> shift) & 1) << 2)
+            as usize
+    }
+
+    pub const fn child_offset(index: usize) -> UVec3 {
+        UVec3::new(
+            (index & 1) as u32,
+            ((index >> 1) & 1) as u32,
+            ((index >> 2) & 1) as u32,
+        )
+    }
+
+    /// Builds a branch out of `children` or a single leaf if they all are the same leaf
+    pub fn collapsed(children: [Node; 8]) -> Self {
+        match &children[0] {
+            Self::Leaf(voxel) if children.iter().all(|child| child == &Self::Leaf(*voxel)) => {
+                Self::Leaf(*voxel)
+            }
+            _ => Self::Branch(Box::new(children)),
+        }
+    }
+
+    pub fn from_chunk_region(chunk: &Chunk, origin: UVec3, level: u32) -> Self {
+        if level == 0 {
+            return Self::Leaf(chunk.get(origin));
+        }
+
+        let half = 1 << (level - 1);
+
+        Self::collapsed(std::array::from_fn(|index| {
+            Self::from_chunk_region(chunk, origin + half * Self::child_offset(index), level - 1)
+        }))
+    }
+
+    pub fn n_nodes(&self) -> usize {
+        match self {
+            Self::Leaf(..) => 1,
+            Self::Branch(children) => 1 + children.iter().map(Self::n_nodes).sum::<usize>(),
+        }
+    }
+}
+
+/// Node layout of the flattened octree as the compute shader reads it
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Pod, Zeroable)]
+pub struct FlatNode {
+    /// [`FlatNode::LEAF`] or [`FlatNode::BRANCH`], leaves keep their material index
+    /// in the bits starting from [`FlatNode::MATERIAL_SHIFT`]
+    pub kind: u32,
+    /// Leaf color or index of the first of 8 consecutive children
+    pub data: u32,
+}
+
+impl FlatNode {
+    pub const LEAF: u32 = 0;
+    pub const BRANCH: u32 = 1;
+    pub const MATERIAL_SHIFT: u32 = 8;
+
+    pub const fn leaf(voxel: Voxel) -> Self {
+        Self {
+            kind: Self::LEAF | (voxel.material as u32) << Self::MATERIAL_SHIFT,
+            data: voxel.color.0,
+        }
+    }
+
+    /// Voxel of a leaf node
+    pub const fn voxel(self) -> Voxel {
+        Voxel::new(Color(self.data), (self.kind >> Self::MATERIAL_SHIFT) as u8)
+    }
+
+    pub const fn branch(first_child: u32) -> Self {
+        Self {
+            kind: Self::BRANCH,
+            data: first_child,
+        }
+    }
+}
+
+/// Sparse voxel octree spanning `2^depth` voxels along each axis
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Octree {
+    pub root: Node,
+    pub depth: u32,
+}
+
+impl Octree {
+    pub const CHUNK_DEPTH: u32 = Chunk::SIZE.trailing_zeros();
+
+    pub const fn new(depth: u32) -> Self {
+        Self {
+            root: Node::EMPTY,
+            depth,
+        }
+    }
+
+    pub const fn size(&self) -> u32 {
+        1 << self.depth
+    }
+
+    pub fn from_chunk(chunk: &Chunk) -> Self {
+        Self {
+            root: Node::from_chunk_region(chunk, UVec3::ZERO, Self::CHUNK_DEPTH),
+            depth: Self::CHUNK_DEPTH,
+        }
+    }
+
+    /// Builds an octree out of chunks placed at given chunk coordinates
+    pub fn from_chunks<'c>(chunks: impl IntoIterator<Item = (UVec3, &'c Chunk)>) -> Self {
+        let chunks = chunks.into_iter().collect::<HashMap<_, _>>();
+
+        let extent = chunks
+            .keys()
+            .map(|pos| pos.max_element() + 1)
+            .max()
+            .unwrap_or(1);
+
+        let depth = Self::CHUNK_DEPTH + extent.next_power_of_two().trailing_zeros();
+
+        fn build(chunks: &HashMap<UVec3, &Chunk>, chunk_pos: UVec3, level: u32) -> Node {
+            if level == 0 {
+                return match chunks.get(&chunk_pos) {
+                    Some(chunk) => Node::from_chunk_region(chunk, UVec3::ZERO, Octree::CHUNK_DEPTH),
+                    None => Node::EMPTY,
+                };
+            }
+
+            let half = 1 << (level - 1);
+
+            Node::collapsed(std::array::from_fn(|index| {
+                build(
+                    chunks,
+                    chunk_pos + half * Node::child_offset(index),
+                    level - 1,
+                )
+            }))
+        }
+
+        Self {
+            root: build(&chunks, UVec3::ZERO, depth - Self::CHUNK_DEPTH),
+            depth,
+        }
+    }
+
+    pub fn contains(&self, pos: UVec3) -> bool {
+        pos.cmplt(UVec3::splat(self.size())).all()
+    }
+
+    /// Voxel at `pos`, out of bounds voxels are empty
+    pub fn get(&self, pos: UVec3) -> Voxel {
+        if !self.contains(pos) {
+            return Voxel::EMPTY;
+        }
+
+        let mut node = &self.root;
+        let mut level = self.depth;
+
+        loop {
+            match node {
+                Node::Leaf(voxel) => return *voxel,
+                Node::Branch(children) => {
+                    node = &children[Node::child_index(pos, level)];
+                    level -= 1;
+                }
+            }
+        }
+    }
+
+    /// # Panic
+    ///
+    /// Panics if `pos` is out of bounds.
+    pub fn set(&mut self, pos: UVec3, voxel: Voxel) {
+        assert!(
+            self.contains(pos),
+            "voxel position {pos} is out of octree bounds"
+        );
+
+        fn set(node: &mut Node, pos: UVec3, level: u32, voxel: Voxel) {
+            if level == 0 {
+                *node = Node::Leaf(voxel);
+                return;
+            }
+
+            if let Node::Leaf(leaf_voxel) = *node {
+                if leaf_voxel == voxel {
+                    return;
+                }
+
+                *node = Node::Branch(Box::new(std::array::from_fn(|_| Node::Leaf(leaf_voxel))));
+            }
+
+            let Node::Branch(children) = node else {
+                unreachable!();
+            };
+
+            set(
+                &mut children[Node::child_index(pos, level)],
+                pos,
+                level - 1,
+                voxel,
+            );
+
+            *node = Node::collapsed(std::mem::take(children.as_mut()));
+        }
+
+        set(&mut self.root, pos, self.depth, voxel);
+    }
+
+    pub fn clear(&mut self, pos: UVec3) {
+        self.set(pos, Voxel::EMPTY);
+    }
+
+    /// Serializes the tree into a node array with the root at index 0
+    /// and children of each branch stored next to each other
+    pub fn flatten(&self) -> Vec<FlatNode> {
+        let mut nodes = Vec::with_capacity(self.root.n_nodes());
+        self.flatten_into(&mut nodes);
+        nodes
+    }
+
+    /// Appends flattened tree to `nodes` and returns the index of its root
+    pub fn flatten_into(&self, nodes: &mut Vec<FlatNode>) -> u32 {
+        fn flatten(node: &Node, index: usize, nodes: &mut Vec<FlatNode>) {
+            match node {
+                Node::Leaf(voxel) => nodes[index] = FlatNode::leaf(*voxel),
+                Node::Branch(children) => {
+                    let first_child = nodes.len();
+                    nodes.resize(first_child + 8, FlatNode::default());
+                    nodes[index] = FlatNode::branch(first_child as u32);
+
+                    for (offset, child) in children.iter().enumerate() {
+                        flatten(child, first_child + offset, nodes);
+                    }
+                }
+            }
+        }
+
+        let root = nodes.len();
+        nodes.push(FlatNode::default());
+
+        flatten(&self.root, root, nodes);
+
+        root as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_positions() -> impl Iterator<Item = UVec3> {
+        let size = Chunk::SIZE as u32;
+        (0..size * size * size)
+            .map(move |index| UVec3::new(index % size, index / size % size, index / (size * size)))
+    }
+
+    /// Walks the flattened tree like the shaders do
+    fn flat_get(nodes: &[FlatNode], root: u32, depth: u32, pos: UVec3) -> Voxel {
+        let mut node = nodes[root as usize];
+        let mut level = depth;
+
+        while FlatNode::BRANCH == node.kind {
+            node = nodes[node.data as usize + Node::child_index(pos, level)];
+            level -= 1;
+        }
+
+        node.voxel()
+    }
+
+    #[test]
+    fn child_index_and_offset_agree() {
+        for level in 1..=4 {
+            for pos in chunk_positions() {
+                let offset = Node::child_offset(Node::child_index(pos, level));
+                assert_eq!((pos >> (level - 1)) & 1, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn uniform_chunk_collapses() {
+        let voxel = Voxel::new(Color::RED, 3);
+        let octree = Octree::from_chunk(&Chunk::all_same(voxel));
+
+        assert_eq!(Node::Leaf(voxel), octree.root);
+        assert_eq!(1, octree.root.n_nodes());
+        assert_eq!(voxel, octree.get(UVec3::splat(7)));
+    }
+
+    #[test]
+    fn single_voxel_splits_down_to_it() {
+        let mut chunk = Chunk::all_same(Voxel::EMPTY);
+        chunk.set(UVec3::new(3, 9, 14), Color::GREEN.into());
+
+        let octree = Octree::from_chunk(&chunk);
+
+        // one branch per level on the way to the voxel
+        assert_eq!(1 + 8 * Octree::CHUNK_DEPTH as usize, octree.root.n_nodes());
+
+        for pos in chunk_positions() {
+            assert_eq!(chunk.get(pos), octree.get(pos), "voxel {pos}");
+        }
+    }
+
+    #[test]
+    fn from_chunk_matches_chunk() {
+        let chunk = Chunk::new_sphere();
+        let octree = Octree::from_chunk(&chunk);
+
+        assert_eq!(Octree::CHUNK_DEPTH, octree.depth);
+
+        for pos in chunk_positions() {
+            assert_eq!(chunk.get(pos), octree.get(pos), "voxel {pos}");
+        }
+    }
+
+    #[test]
+    fn from_chunks_places_chunks() {
+        let sphere = Chunk::new_sphere();
+        let octree = Octree::from_chunks([(UVec3::new(2, 0, 1), &sphere)]);
+
+        // the chunk extent of 3 rounds up to 4 chunks
+        assert_eq!(Octree::CHUNK_DEPTH + 2, octree.depth);
+
+        let origin = Chunk::SIZE as u32 * UVec3::new(2, 0, 1);
+
+        for pos in chunk_positions() {
+            assert_eq!(sphere.get(pos), octree.get(origin + pos), "voxel {pos}");
+            assert!(octree.get(pos).is_empty());
+        }
+    }
+
+    #[test]
+    fn get_out_of_bounds_is_empty() {
+        let octree = Octree::from_chunk(&Chunk::all_same(Color::BLUE.into()));
+
+        assert!(octree.get(UVec3::new(0, 16, 0)).is_empty());
+        assert!(octree.get(UVec3::MAX).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of octree bounds")]
+    fn set_out_of_bounds_panics() {
+        Octree::new(2).set(UVec3::new(4, 0, 0), Color::RED.into());
+    }
+
+    #[test]
+    fn set_splits_and_merges() {
+        let mut octree = Octree::new(3);
+        let pos = UVec3::new(5, 2, 7);
+
+        octree.set(pos, Color::RED.into());
+
+        assert_eq!(1 + 8 * 3, octree.root.n_nodes());
+        assert_eq!(Voxel::from(Color::RED), octree.get(pos));
+        assert!(octree.get(pos - UVec3::X).is_empty());
+
+        octree.clear(pos);
+
+        assert_eq!(Node::EMPTY, octree.root);
+    }
+
+    #[test]
+    fn set_same_voxel_keeps_leaf() {
+        let voxel = Voxel::new(Color::GREEN, 1);
+        let mut octree = Octree {
+            root: Node::Leaf(voxel),
+            depth: 3,
+        };
+
+        octree.set(UVec3::ONE, voxel);
+
+        assert_eq!(Node::Leaf(voxel), octree.root);
+    }
+
+    #[test]
+    fn filling_all_voxels_merges_into_leaf() {
+        let voxel = Voxel::new(Color::BLUE, 2);
+        let mut octree = Octree::new(2);
+
+        for pos in chunk_positions().filter(|pos| pos.cmplt(UVec3::splat(4)).all()) {
+            octree.set(pos, voxel);
+        }
+
+        assert_eq!(Node::Leaf(voxel), octree.root);
+
+        // a different material is a different voxel
+        octree.set(UVec3::ZERO, Voxel::new(Color::BLUE, 3));
+
+        assert_eq!(1 + 8 + 8, octree.root.n_nodes());
+    }
+
+    #[test]
+    fn flatten_round_trips() {
+        let mut chunk = Chunk::new_sphere();
+        chunk.set(UVec3::new(1, 2, 3), Voxel::new(Color::RED, 7));
+
+        let octree = Octree::from_chunk(&chunk);
+        let nodes = octree.flatten();
+
+        assert_eq!(octree.root.n_nodes(), nodes.len());
+
+        for pos in chunk_positions() {
+            assert_eq!(
+                octree.get(pos),
+                flat_get(&nodes, 0, octree.depth, pos),
+                "voxel {pos}"
+            );
+        }
+    }
+
+    #[test]
+    fn flatten_into_appends() {
+        let octree = Octree::from_chunk(&Chunk::new_sphere());
+        let mut nodes = vec![FlatNode::leaf(Color::RED.into()); 5];
+        let root = octree.flatten_into(&mut nodes);
+
+        assert_eq!(5, root);
+        assert_eq!(FlatNode::leaf(Color::RED.into()), nodes[4]);
+
+        for pos in chunk_positions() {
+            assert_eq!(
+                octree.get(pos),
+                flat_get(&nodes, root, octree.depth, pos),
+                "voxel {pos}"
+            );
+        }
+    }
+
+    #[test]
+    fn flat_leaf_keeps_material() {
+        let voxel = Voxel::new(Color::rgb8(1, 2, 3), 255);
+
+        assert_eq!(voxel, FlatNode::leaf(voxel).voxel());
+        assert_eq!(FlatNode::LEAF, FlatNode::leaf(voxel).kind & 0xff);
+    }
+}
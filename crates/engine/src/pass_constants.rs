@@ -0,0 +1,118 @@
+use crate::context::RenderContext;
+use bytemuck::Pod;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use wgpu::*;
+
+/// Per-pass constants set with push constants if the device supports them,
+/// otherwise written into a uniform buffer bound at `binding`.
+/// Shaders declare the block under `#ifdef PUSH_CONSTANTS`, see [`PassConstants::defines`]
+pub struct PassConstants<T> {
+    pub stages: ShaderStages,
+    pub binding: u32,
+    /// `None` if push constants are used
+    pub buffer: Option<Buffer>,
+    _data: PhantomData<T>,
+}
+
+impl<T: Pod> PassConstants<T> {
+    pub const SIZE: u32 = std::mem::size_of::<T>() as u32;
+
+    pub fn new(context: &RenderContext, label: &str, stages: ShaderStages, binding: u32) -> Self {
+        let buffer = (!context.supports_push_constants()).then(|| {
+            context.device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                // std140 blocks are padded to 16 bytes
+                size: Self::SIZE.next_multiple_of(16) as u64,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+
+        Self {
+            stages,
+            binding,
+            buffer,
+            _data: PhantomData,
+        }
+    }
+
+    pub fn defines(&self) -> naga::FastHashMap<String, String> {
+        let mut defines = naga::FastHashMap::default();
+
+        if self.buffer.is_none() {
+            defines.insert("PUSH_CONSTANTS".to_owned(), String::new());
+        }
+
+        defines
+    }
+
+    /// Compiles a GLSL shader with [`PassConstants::defines`]
+    pub fn create_shader_module(
+        &self,
+        context: &RenderContext,
+        label: &str,
+        source: &'static str,
+        stage: naga::ShaderStage,
+    ) -> ShaderModule {
+        context.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(label),
+            source: ShaderSource::Glsl {
+                shader: Cow::Borrowed(source),
+                stage,
+                defines: self.defines(),
+            },
+        })
+    }
+
+    pub fn push_constant_ranges(&self) -> Vec<PushConstantRange> {
+        match self.buffer {
+            Some(_) => vec![],
+            None => vec![PushConstantRange {
+                stages: self.stages,
+                range: 0..Self::SIZE,
+            }],
+        }
+    }
+
+    pub fn layout_entry(&self) -> Option<BindGroupLayoutEntry> {
+        self.buffer.as_ref().map(|buffer| BindGroupLayoutEntry {
+            binding: self.binding,
+            visibility: self.stages,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(buffer.size()),
+            },
+            count: None,
+        })
+    }
+
+    pub fn bind_group_entry(&self) -> Option<BindGroupEntry<'_>> {
+        self.buffer.as_ref().map(|buffer| BindGroupEntry {
+            binding: self.binding,
+            resource: buffer.as_entire_binding(),
+        })
+    }
+
+    /// Buffer writes happen on the next submission,
+    /// so all passes submitted together see the last value
+    pub fn set_compute(&self, context: &RenderContext, pass: &mut ComputePass<'_>, data: &T) {
+        match self.buffer.as_ref() {
+            Some(buffer) => context
+                .queue
+                .write_buffer(buffer, 0, bytemuck::bytes_of(data)),
+            None => pass.set_push_constants(0, bytemuck::bytes_of(data)),
+        }
+    }
+
+    /// See [`PassConstants::set_compute`]
+    pub fn set_render(&self, context: &RenderContext, pass: &mut RenderPass<'_>, data: &T) {
+        match self.buffer.as_ref() {
+            Some(buffer) => context
+                .queue
+                .write_buffer(buffer, 0, bytemuck::bytes_of(data)),
+            None => pass.set_push_constants(self.stages, 0, bytemuck::bytes_of(data)),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use crate::context::RenderContext;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use tracing::debug;
+use wgpu::*;
+
+/// GPU passes timed by [`GpuProfiler`]
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GpuPass {
+    /// Compute dispatch raytracing the render texture
+    Raytrace = 0,
+    /// Screen quad blit tonemapping the render texture onto the target
+    ScreenQuad = 1,
+}
+
+impl GpuPass {
+    pub const ALL: [Self; 2] = [Self::Raytrace, Self::ScreenQuad];
+
+    const fn begin_query(self) -> u32 {
+        2 * self as u32
+    }
+
+    const fn end_query(self) -> u32 {
+        2 * self as u32 + 1
+    }
+}
+
+/// Statistics over the latest [`GpuProfiler::WINDOW`] frames in milliseconds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PassStats {
+    pub mean: f32,
+    pub median: f32,
+    pub p95: f32,
+    pub max: f32,
+}
+
+impl PassStats {
+    /// `None` for no samples
+    pub fn from_samples(samples: impl IntoIterator<Item = f32>) -> Option<Self> {
+        let mut samples = samples.into_iter().collect::<Vec<_>>();
+
+        if samples.is_empty() {
+            return None;
+        }
+
+        samples.sort_by(f32::total_cmp);
+
+        let last = samples.len() - 1;
+        let percentile = |fraction: f32| samples[(fraction * last as f32).round() as usize];
+
+        Some(Self {
+            mean: samples.iter().sum::<f32>() / samples.len() as f32,
+            median: percentile(0.5),
+            p95: percentile(0.95),
+            max: samples[last],
+        })
+    }
+}
+
+/// Readback buffer the timestamps of one frame are copied into
+struct Readback {
+    buffer: Buffer,
+    /// One of the `Readback::*` states, set from the mapping callback
+    state: Arc<AtomicU8>,
+    /// Bit per [`GpuPass`] written during the frame
+    passes: u32,
+}
+
+impl Readback {
+    const FREE: u8 = 0;
+    const PENDING: u8 = 1;
+    const MAPPED: u8 = 2;
+    const FAILED: u8 = 3;
+}
+
+/// Times the GPU passes with timestamp queries and reads the results back without stalling,
+/// frames are skipped while all readback buffers are still in flight
+pub struct GpuProfiler {
+    pub query_set: QuerySet,
+    pub resolve_buffer: Buffer,
+    readbacks: Vec<Readback>,
+    /// Readback the current frame is recorded into
+    current: Option<usize>,
+    /// Nanoseconds per timestamp tick
+    pub timestamp_period: f32,
+    /// Latest pass durations in milliseconds indexed by [`GpuPass`]
+    pub history: [VecDeque<f32>; GpuPass::ALL.len()],
+    /// Frames read back since the last stats log
+    n_resolved_frames: u32,
+}
+
+impl GpuProfiler {
+    pub const N_QUERIES: u32 = 2 * GpuPass::ALL.len() as u32;
+    pub const N_READBACKS: usize = 4;
+    /// Frames the stats are computed over
+    pub const WINDOW: usize = 128;
+    /// Stats are logged once per this many resolved frames
+    pub const LOG_INTERVAL: u32 = 128;
+
+    /// Returns `None` if the device has no [`Features::TIMESTAMP_QUERY`]
+    pub fn new(context: &RenderContext) -> Option<Self> {
+        if !context
+            .device
+            .features()
+            .contains(Features::TIMESTAMP_QUERY)
+        {
+            return None;
+        }
+
+        let size = Self::N_QUERIES as u64 * QUERY_SIZE as u64;
+
+        let query_set = context.device.create_query_set(&QuerySetDescriptor {
+            label: Some("gpu-profiler"),
+            ty: QueryType::Timestamp,
+            count: Self::N_QUERIES,
+        });
+
+        let resolve_buffer = context.device.create_buffer(&BufferDescriptor {
+            label: Some("gpu-profiler-resolve"),
+            size,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readbacks = (0..Self::N_READBACKS)
+            .map(|_| Readback {
+                buffer: context.device.create_buffer(&BufferDescriptor {
+                    label: Some("gpu-profiler-readback"),
+                    size,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                state: Arc::new(AtomicU8::new(Readback::FREE)),
+                passes: 0,
+            })
+            .collect();
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readbacks,
+            current: None,
+            timestamp_period: context.queue.get_timestamp_period(),
+            history: Default::default(),
+            n_resolved_frames: 0,
+        })
+    }
+
+    /// Picks a free readback for the next frame, returns `false` if the frame is not timed
+    pub fn begin_frame(&mut self) -> bool {
+        self.current = self
+            .readbacks
+            .iter()
+            .position(|readback| Readback::FREE == readback.state.load(Ordering::Acquire));
+
+        if let Some(index) = self.current {
+            self.readbacks[index].passes = 0;
+        }
+
+        self.current.is_some()
+    }
+
+    fn mark_pass(&mut self, pass: GpuPass) -> bool {
+        let Some(index) = self.current else {
+            return false;
+        };
+
+        self.readbacks[index].passes |= 1 << pass as u32;
+        true
+    }
+
+    pub fn compute_timestamp_writes(
+        &mut self,
+        pass: GpuPass,
+    ) -> Option<ComputePassTimestampWrites<'_>> {
+        self.mark_pass(pass).then_some(ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass.begin_query()),
+            end_of_pass_write_index: Some(pass.end_query()),
+        })
+    }
+
+    pub fn render_timestamp_writes(
+        &mut self,
+        pass: GpuPass,
+    ) -> Option<RenderPassTimestampWrites<'_>> {
+        self.mark_pass(pass).then_some(RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass.begin_query()),
+            end_of_pass_write_index: Some(pass.end_query()),
+        })
+    }
+
+    /// Copies the timestamps of the frame into its readback, call it after encoding all passes
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        let Some(index) = self.current else {
+            return;
+        };
+
+        encoder.resolve_query_set(&self.query_set, 0..Self::N_QUERIES, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readbacks[index].buffer,
+            0,
+            self.resolve_buffer.size(),
+        );
+    }
+
+    /// Starts reading the frame back, call it after submitting the frame
+    pub fn end_frame(&mut self) {
+        let Some(index) = self.current.take() else {
+            return;
+        };
+
+        let readback = &self.readbacks[index];
+
+        if 0 == readback.passes {
+            return;
+        }
+
+        let state = Arc::clone(&readback.state);
+        state.store(Readback::PENDING, Ordering::Release);
+
+        readback
+            .buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                let new_state = match result {
+                    Ok(()) => Readback::MAPPED,
+                    Err(_) => Readback::FAILED,
+                };
+
+                state.store(new_state, Ordering::Release);
+            });
+    }
+
+    /// Collects the frames read back so far without waiting for the rest
+    pub fn poll(&mut self, device: &Device) {
+        device.poll(Maintain::Poll);
+
+        for index in 0..self.readbacks.len() {
+            let readback = &self.readbacks[index];
+
+            match readback.state.load(Ordering::Acquire) {
+                Readback::MAPPED => {}
+                Readback::FAILED => {
+                    readback.state.store(Readback::FREE, Ordering::Release);
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let timestamps = bytemuck::pod_collect_to_vec::<u8, u64>(
+                &readback.buffer.slice(..).get_mapped_range(),
+            );
+            let passes = readback.passes;
+
+            readback.buffer.unmap();
+            readback.state.store(Readback::FREE, Ordering::Release);
+
+            for pass in GpuPass::ALL {
+                if 0 == passes & (1 << pass as u32) {
+                    continue;
+                }
+
+                let begin = timestamps[pass.begin_query() as usize];
+                let end = timestamps[pass.end_query() as usize];
+
+                // timestamps are not guaranteed to be monotonic across passes
+                let Some(ticks) = end.checked_sub(begin) else {
+                    continue;
+                };
+
+                let history = &mut self.history[pass as usize];
+
+                if history.len() == Self::WINDOW {
+                    history.pop_front();
+                }
+
+                history.push_back(ticks as f32 * self.timestamp_period * 1.0e-6);
+            }
+
+            self.n_resolved_frames += 1;
+
+            if self.n_resolved_frames >= Self::LOG_INTERVAL {
+                self.n_resolved_frames = 0;
+                self.log_stats();
+            }
+        }
+    }
+
+    pub fn stats(&self, pass: GpuPass) -> Option<PassStats> {
+        PassStats::from_samples(self.history[pass as usize].iter().copied())
+    }
+
+    pub fn log_stats(&self) {
+        for pass in GpuPass::ALL {
+            let Some(stats) = self.stats(pass) else {
+                continue;
+            };
+
+            debug!(
+                ?pass,
+                mean_ms = stats.mean,
+                median_ms = stats.median,
+                p95_ms = stats.p95,
+                max_ms = stats.max,
+                "gpu pass time"
+            );
+        }
+    }
+}
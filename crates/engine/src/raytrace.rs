@@ -0,0 +1,1166 @@
+//! CPU port of the traversal and the raytrace mode shading in `voxels-demo-compute.glsl`,
+//! keep both implementations in sync. Path tracing is GPU only.
+
+use crate::camera::Camera;
+use crate::capture::Image;
+use crate::environment::Environment;
+use crate::light::Light;
+use crate::material::Material;
+use crate::octree::{Chunk, FlatNode, Node, Octree, Voxel};
+use crate::shading::{AmbientOcclusion, DebugView};
+use crate::tonemapping::{linear_to_srgb, srgb_to_linear, Tonemapping};
+use crate::world::FlatWorld;
+use glam::*;
+use std::f32::consts::PI;
+
+pub const INFINITY: f32 = 1.0e30;
+pub const MAX_TRAVERSAL_STEPS: u32 = 1024;
+pub const SURFACE_OFFSET: f32 = 1.0e-3;
+pub const AMBIENT_OCCLUSION_SAMPLES: u32 = 8;
+pub const AMBIENT_OCCLUSION_DISTANCE: f32 = 4.0;
+pub const MAX_TRANSPARENT_LAYERS: u32 = 8;
+pub const OPAQUE_ALPHA: f32 = 0.99;
+pub const DEBUG_MAX_STEPS: f32 = 64.0;
+pub const DEBUG_LINE_WIDTH: f32 = 0.05;
+
+pub const fn pcg_hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// The compute shader's per-pixel random number generator
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rng {
+    pub state: u32,
+}
+
+impl Rng {
+    pub const fn new(pixel_index: u32, sample_index: u32) -> Self {
+        Self {
+            state: pcg_hash(pixel_index ^ pcg_hash(sample_index)),
+        }
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn random_float(&mut self) -> f32 {
+        self.state = pcg_hash(self.state);
+        (self.state >> 8) as f32 / 16777216.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub inverse_direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction,
+            inverse_direction: direction.recip(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + t * self.direction
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RayAabbHit {
+    pub distance_near: f32,
+    pub distance_far: f32,
+    pub has_hit: bool,
+}
+
+/// Distances along the ray to the near and far planes of the box slabs,
+/// rays parallel to a slab stay within it all the way if they start in `lo..hi`
+pub fn ray_slab_distances(lo: Vec3, hi: Vec3, ray: &Ray) -> (Vec3, Vec3) {
+    let tbot = ray.inverse_direction * (lo - ray.origin);
+    let ttop = ray.inverse_direction * (hi - ray.origin);
+    let is_parallel = ray.direction.cmpeq(Vec3::ZERO);
+    let is_inside = ray.origin.cmpge(lo) & ray.origin.cmplt(hi);
+    let parallel_tmin = Vec3::select(is_inside, Vec3::splat(-INFINITY), Vec3::splat(INFINITY));
+
+    (
+        Vec3::select(is_parallel, parallel_tmin, ttop.min(tbot)),
+        Vec3::select(is_parallel, -parallel_tmin, ttop.max(tbot)),
+    )
+}
+
+pub fn ray_aabb_intersect(lo: Vec3, hi: Vec3, ray: &Ray) -> RayAabbHit {
+    let (tmin, tmax) = ray_slab_distances(lo, hi, ray);
+
+    let t0 = tmin.max_element();
+    let t1 = tmax.min_element();
+
+    RayAabbHit {
+        distance_near: t0,
+        distance_far: t1,
+        has_hit: t1 > t0.max(0.0),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RaytraceResult {
+    pub color: Vec4,
+    pub voxel: Voxel,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub has_hit: bool,
+    /// Leaves visited by the traversal
+    pub steps: u32,
+}
+
+/// Cube of same voxels found by [`VoxelVolume::find_leaf`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Leaf {
+    pub voxel: Voxel,
+    pub lo: IVec3,
+    pub size: i32,
+}
+
+/// Voxel data the raytracer can walk through
+pub trait VoxelVolume {
+    /// World-space voxel range `lo..hi` of the volume
+    fn bounds(&self) -> (IVec3, IVec3);
+
+    /// Largest uniform cube containing voxel `pos` which lies within [`VoxelVolume::bounds`]
+    fn find_leaf(&self, pos: IVec3) -> Leaf;
+}
+
+impl VoxelVolume for Chunk {
+    fn bounds(&self) -> (IVec3, IVec3) {
+        (IVec3::ZERO, IVec3::splat(Chunk::SIZE as i32))
+    }
+
+    fn find_leaf(&self, pos: IVec3) -> Leaf {
+        Leaf {
+            voxel: self.get(pos.as_uvec3()),
+            lo: pos,
+            size: 1,
+        }
+    }
+}
+
+impl VoxelVolume for Octree {
+    fn bounds(&self) -> (IVec3, IVec3) {
+        (IVec3::ZERO, IVec3::splat(self.size() as i32))
+    }
+
+    fn find_leaf(&self, pos: IVec3) -> Leaf {
+        let pos = pos.as_uvec3();
+        let mut node = &self.root;
+        let mut level = self.depth;
+        let mut lo = UVec3::ZERO;
+
+        loop {
+            match node {
+                Node::Leaf(voxel) => {
+                    return Leaf {
+                        voxel: *voxel,
+                        lo: lo.as_ivec3(),
+                        size: 1 << level,
+                    }
+                }
+                Node::Branch(children) => {
+                    let index = Node::child_index(pos, level);
+
+                    level -= 1;
+                    lo += (1 << level) * Node::child_offset(index);
+                    node = &children[index];
+                }
+            }
+        }
+    }
+}
+
+impl VoxelVolume for FlatWorld {
+    fn bounds(&self) -> (IVec3, IVec3) {
+        let chunk_size = Chunk::SIZE as i32;
+        let hi = self.chunk_lo + self.chunk_count.as_ivec3();
+
+        (chunk_size * self.chunk_lo, chunk_size * hi)
+    }
+
+    fn find_leaf(&self, pos: IVec3) -> Leaf {
+        let chunk_pos = pos >> Octree::CHUNK_DEPTH as i32;
+        let chunk_origin = Chunk::SIZE as i32 * chunk_pos;
+        let table_pos = (chunk_pos - self.chunk_lo).as_uvec3();
+
+        let root = self.chunk_roots[FlatWorld::chunk_index(self.chunk_count, table_pos)];
+
+        if FlatWorld::NO_CHUNK == root {
+            return Leaf {
+                voxel: Voxel::EMPTY,
+                lo: chunk_origin,
+                size: Chunk::SIZE as i32,
+            };
+        }
+
+        let mut index = root as usize;
+        let mut size = Chunk::SIZE as i32;
+        let mut lo = IVec3::ZERO;
+        let pos = pos - chunk_origin;
+
+        while FlatNode::BRANCH == self.nodes[index].kind {
+            size /= 2;
+
+            let upper_half = pos.cmpge(lo + size);
+            lo += IVec3::select(upper_half, IVec3::splat(size), IVec3::ZERO);
+            index = self.nodes[index].data as usize + upper_half.bitmask() as usize;
+        }
+
+        Leaf {
+            voxel: self.nodes[index].voxel(),
+            lo: lo + chunk_origin,
+            size,
+        }
+    }
+}
+
+fn min_axis(v: Vec3) -> usize {
+    if v.x < v.y {
+        if v.x < v.z {
+            0
+        } else {
+            2
+        }
+    } else if v.y < v.z {
+        1
+    } else {
+        2
+    }
+}
+
+fn max_axis(v: Vec3) -> usize {
+    min_axis(-v)
+}
+
+/// `medium` is the voxel the ray travels through, the ray hits the first voxel of another medium
+/// and leaving the world counts as hitting an empty voxel
+pub fn raytrace(volume: &impl VoxelVolume, ray: &Ray, medium: Voxel) -> RaytraceResult {
+    let (world_lo, world_hi) = volume.bounds();
+
+    let aabb_hit = ray_aabb_intersect(world_lo.as_vec3(), world_hi.as_vec3(), ray);
+
+    if !aabb_hit.has_hit {
+        return RaytraceResult {
+            position: ray.origin,
+            ..Default::default()
+        };
+    }
+
+    let origin = ray.origin;
+    // same as GLSL `sign()` which is zero for zero components
+    let step = Vec3::select(
+        ray.direction.cmpeq(Vec3::ZERO),
+        Vec3::ZERO,
+        ray.direction.signum(),
+    )
+    .as_ivec3();
+
+    let mut t = aabb_hit.distance_near.max(0.0);
+    let mut int_pos = ray.at(t).floor().as_ivec3().clamp(world_lo, world_hi - 1);
+
+    // the ray either starts inside the world or enters it through the face of the latest slab
+    let mut stepped_index = None;
+
+    if aabb_hit.distance_near > 0.0 {
+        let (tmin, _) = ray_slab_distances(world_lo.as_vec3(), world_hi.as_vec3(), ray);
+        stepped_index = Some(max_axis(tmin));
+    }
+
+    let face_normal = |stepped_index: Option<usize>| match stepped_index {
+        Some(index) => -(step[index] as f32) * Vec3::AXES[index],
+        None => Vec3::ZERO,
+    };
+
+    let mut has_left_world = false;
+    let mut n_steps = 0;
+
+    for _ in 0..MAX_TRAVERSAL_STEPS {
+        let leaf = volume.find_leaf(int_pos);
+        n_steps += 1;
+
+        if !is_same_medium(medium, leaf.voxel) {
+            return RaytraceResult {
+                color: leaf.voxel.color.to_linear(),
+                voxel: leaf.voxel,
+                position: ray.at(t),
+                normal: face_normal(stepped_index),
+                has_hit: true,
+                steps: n_steps,
+            };
+        }
+
+        // skip the whole leaf at once by jumping to its exit face
+        let exit_bound = (leaf.lo + step.max(IVec3::ZERO) * leaf.size).as_vec3();
+        let mut tmax = (exit_bound - origin) * ray.inverse_direction;
+
+        for axis in 0..3 {
+            if 0 == step[axis] {
+                tmax[axis] = INFINITY;
+            }
+        }
+
+        let index = min_axis(tmax);
+        stepped_index = Some(index);
+        t = tmax[index];
+
+        if t > aabb_hit.distance_far {
+            has_left_world = true;
+            break;
+        }
+
+        int_pos = ray
+            .at(t)
+            .floor()
+            .as_ivec3()
+            .clamp(leaf.lo, leaf.lo + leaf.size - 1);
+
+        int_pos[index] = if step[index] > 0 {
+            leaf.lo[index] + leaf.size
+        } else {
+            leaf.lo[index] - 1
+        };
+
+        if int_pos.cmplt(world_lo).any() || int_pos.cmpge(world_hi).any() {
+            has_left_world = true;
+            break;
+        }
+    }
+
+    if has_left_world && !medium.is_empty() {
+        return RaytraceResult {
+            position: ray.at(t),
+            normal: face_normal(stepped_index),
+            has_hit: true,
+            steps: n_steps,
+            ..Default::default()
+        };
+    }
+
+    RaytraceResult {
+        position: ray.at(t),
+        steps: n_steps,
+        ..Default::default()
+    }
+}
+
+/// Voxels of one material make a single medium without surfaces between them
+pub fn is_same_medium(lhs: Voxel, rhs: Voxel) -> bool {
+    lhs.is_empty() == rhs.is_empty() && lhs.material == rhs.material
+}
+
+pub fn is_occluded(
+    volume: &impl VoxelVolume,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> bool {
+    let result = raytrace(volume, &Ray::new(origin, direction), Voxel::EMPTY);
+    result.has_hit && origin.distance(result.position) < max_distance
+}
+
+fn material(materials: &[Material], voxel: Voxel) -> Material {
+    materials
+        .get(voxel.material as usize)
+        .copied()
+        .unwrap_or_default()
+}
+
+fn voxel_opacity(materials: &[Material], result: &RaytraceResult) -> f32 {
+    result.color.w * material(materials, result.voxel).opacity
+}
+
+/// Fraction of light passing through the voxels on the way, every transparent surface lets
+/// `1 - opacity` of it through and opaque voxels block it all
+pub fn transmittance(
+    volume: &impl VoxelVolume,
+    materials: &[Material],
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> f32 {
+    let mut ray = Ray::new(origin, direction);
+    let mut medium = Voxel::EMPTY;
+    let mut transmitted = 1.0;
+
+    for _ in 0..MAX_TRANSPARENT_LAYERS {
+        let result = raytrace(volume, &ray, medium);
+
+        if !result.has_hit || origin.distance(result.position) >= max_distance {
+            return transmitted;
+        }
+
+        if !result.voxel.is_empty() {
+            transmitted *= 1.0 - voxel_opacity(materials, &result);
+
+            if transmitted <= 0.0 {
+                return 0.0;
+            }
+        }
+
+        medium = result.voxel;
+        ray.origin = result.position + SURFACE_OFFSET * direction;
+    }
+
+    0.0
+}
+
+fn index_of_refraction(materials: &[Material], voxel: Voxel) -> f32 {
+    if voxel.is_empty() {
+        1.0
+    } else {
+        material(materials, voxel).ior
+    }
+}
+
+/// Same as GLSL `refract()`, zero on total internal reflection
+pub fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Vec3 {
+    let cos_theta = normal.dot(direction);
+    let k = 1.0 - eta * eta * (1.0 - cos_theta * cos_theta);
+
+    if k < 0.0 {
+        Vec3::ZERO
+    } else {
+        eta * direction - (eta * cos_theta + k.sqrt()) * normal
+    }
+}
+
+/// Same as GLSL `reflect()`
+pub fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+    direction - 2.0 * normal.dot(direction) * normal
+}
+
+/// Direction of a camera ray going from `medium` into `voxel` through a surface with `normal`
+/// facing the ray, the ray only reflects on total internal reflection and
+/// `medium` becomes `voxel` unless it does
+pub fn pass_surface(
+    materials: &[Material],
+    refraction: bool,
+    direction: Vec3,
+    normal: Vec3,
+    medium: &mut Voxel,
+    voxel: Voxel,
+) -> Vec3 {
+    if !refraction {
+        *medium = voxel;
+        return direction;
+    }
+
+    let eta = index_of_refraction(materials, *medium) / index_of_refraction(materials, voxel);
+    let refracted = refract(direction, normal, eta);
+
+    if Vec3::ZERO == refracted {
+        return reflect(direction, normal);
+    }
+
+    *medium = voxel;
+    refracted
+}
+
+/// Radiance reflected by a white surface, shadow rays start off the surface along the normal
+pub fn direct_lighting(
+    volume: &impl VoxelVolume,
+    shading: &Shading<'_>,
+    position: Vec3,
+    normal: Vec3,
+) -> Vec3 {
+    let origin = position + SURFACE_OFFSET * normal;
+    let mut radiance = Vec3::ZERO;
+
+    for light in shading.lights {
+        let (direction, distance) = light.direction_from(origin);
+        let cos_theta = direction.dot(normal);
+
+        if cos_theta <= 0.0 {
+            continue;
+        }
+
+        let visibility = if shading.shadows {
+            transmittance(volume, shading.materials, origin, direction, distance)
+        } else {
+            1.0
+        };
+
+        radiance += light.color() * cos_theta * visibility;
+    }
+
+    radiance
+}
+
+pub fn cosine_sample_hemisphere(rng: &mut Rng, normal: Vec3) -> Vec3 {
+    let phi = 2.0 * PI * rng.random_float();
+    let sin_theta_squared = rng.random_float();
+    let sin_theta = sin_theta_squared.sqrt();
+
+    let up = if normal.y.abs() < 0.5 {
+        Vec3::Y
+    } else {
+        Vec3::X
+    };
+    let tangent = normal.cross(up).normalize();
+    let bitangent = normal.cross(tangent);
+
+    (sin_theta * phi.cos() * tangent
+        + sin_theta * phi.sin() * bitangent
+        + (1.0 - sin_theta_squared).sqrt() * normal)
+        .normalize()
+}
+
+pub fn is_solid(volume: &impl VoxelVolume, pos: IVec3) -> bool {
+    let (lo, hi) = volume.bounds();
+
+    if pos.cmplt(lo).any() || pos.cmpge(hi).any() {
+        return false;
+    }
+
+    !volume.find_leaf(pos).voxel.is_empty()
+}
+
+/// Occlusion of the face corners by the 8 voxels around the cell in front of the face,
+/// interpolated over the face
+pub fn neighbors_ambient_occlusion(volume: &impl VoxelVolume, position: Vec3, normal: Vec3) -> f32 {
+    let axis = max_axis(normal.abs());
+    let u_axis = (axis + 1) % 3;
+    let v_axis = (axis + 2) % 3;
+
+    let front = (position + 0.5 * normal).floor().as_ivec3();
+    let mut u = IVec3::ZERO;
+    let mut v = IVec3::ZERO;
+    u[u_axis] = 1;
+    v[v_axis] = 1;
+
+    let occluded = |pos| is_solid(volume, pos) as u32 as f32;
+    let u_sides = [occluded(front - u), occluded(front + u)];
+    let v_sides = [occluded(front - v), occluded(front + v)];
+
+    let corners = [0, 1, 2, 3].map(|i| {
+        let u_side = i & 1;
+        let v_side = i >> 1;
+        let u_occluded = u_sides[u_side as usize];
+        let v_occluded = v_sides[v_side as usize];
+        let corner_occluded = occluded(front + (2 * u_side - 1) * u + (2 * v_side - 1) * v);
+
+        if u_occluded * v_occluded > 0.0 {
+            0.0
+        } else {
+            (3.0 - u_occluded - v_occluded - corner_occluded) / 3.0
+        }
+    });
+
+    let face_coord = Vec2::new(position[u_axis], position[v_axis]).fract_gl();
+
+    let lower = corners[0] + (corners[1] - corners[0]) * face_coord.x;
+    let upper = corners[2] + (corners[3] - corners[2]) * face_coord.x;
+
+    lower + (upper - lower) * face_coord.y
+}
+
+/// Fraction of short cosine-weighted rays escaping the surface
+pub fn traced_ambient_occlusion(
+    volume: &impl VoxelVolume,
+    rng: &mut Rng,
+    position: Vec3,
+    normal: Vec3,
+) -> f32 {
+    let origin = position + SURFACE_OFFSET * normal;
+
+    let n_escaped = (0..AMBIENT_OCCLUSION_SAMPLES)
+        .filter(|_| {
+            let direction = cosine_sample_hemisphere(rng, normal);
+            !is_occluded(volume, origin, direction, AMBIENT_OCCLUSION_DISTANCE)
+        })
+        .count();
+
+    n_escaped as f32 / AMBIENT_OCCLUSION_SAMPLES as f32
+}
+
+pub fn ambient_occlusion(
+    volume: &impl VoxelVolume,
+    mode: AmbientOcclusion,
+    rng: &mut Rng,
+    position: Vec3,
+    normal: Vec3,
+) -> f32 {
+    // rays starting inside a voxel have no surface to occlude
+    if Vec3::ZERO == normal {
+        return 1.0;
+    }
+
+    match mode {
+        AmbientOcclusion::Off => 1.0,
+        AmbientOcclusion::Neighbors => neighbors_ambient_occlusion(volume, position, normal),
+        AmbientOcclusion::Traced => traced_ambient_occlusion(volume, rng, position, normal),
+    }
+}
+
+/// Everything [`shade_pixel`] shades the hit voxels with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shading<'s> {
+    pub lights: &'s [Light],
+    /// Material table, missing entries are default materials
+    pub materials: &'s [Material],
+    pub shadows: bool,
+    pub refraction: bool,
+    pub ambient_occlusion: AmbientOcclusion,
+    pub debug_view: DebugView,
+    pub environment: &'s Environment,
+    /// [`Environment::ambient`] around +X, -X, +Y, -Y, +Z and -Z, see [`Environment::to_uniform`]
+    pub ambient: [Vec4; 6],
+}
+
+/// Environment light reflected by a white surface facing along `normal`
+pub fn ambient_radiance(ambient: &[Vec4; 6], normal: Vec3) -> Vec3 {
+    let axis = max_axis(normal.abs());
+    ambient[2 * axis + (normal[axis] < 0.0) as usize].xyz()
+}
+
+/// Light a metal surface reflects towards the viewer, a single reflection ray is traced
+/// and widens towards the diffuse lobe with roughness like in the path tracer
+pub fn specular_radiance(
+    volume: &impl VoxelVolume,
+    shading: &Shading<'_>,
+    position: Vec3,
+    normal: Vec3,
+    direction: Vec3,
+    roughness: f32,
+    medium: Voxel,
+) -> Vec3 {
+    let reflected = reflect(direction, normal);
+    let result = raytrace(
+        volume,
+        &Ray::new(position + SURFACE_OFFSET * normal, reflected),
+        medium,
+    );
+
+    // reflections of reflections are not traced, reflected voxels are shaded as diffuse
+    let mirror = match result.has_hit && !result.voxel.is_empty() {
+        true => {
+            let material = material(shading.materials, result.voxel);
+
+            (direct_lighting(volume, shading, result.position, result.normal)
+                + ambient_radiance(&shading.ambient, result.normal))
+                * result.color.xyz()
+                * material.albedo
+                + material.emission
+        }
+        false => shading.environment.radiance(reflected),
+    };
+
+    let diffuse = direct_lighting(volume, shading, position, normal)
+        + ambient_radiance(&shading.ambient, normal);
+
+    mirror.lerp(diffuse, roughness * roughness)
+}
+
+/// Blue through green and yellow to red for `t` from 0 to 1
+pub fn heatmap(t: f32) -> Vec3 {
+    let t = t.clamp(0.0, 1.0);
+    (1.5 - (4.0 * t - Vec3::new(3.0, 2.0, 1.0)).abs()).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+/// Distance from `position` on a face with `normal` to the edges of the box on that face
+pub fn distance_to_box_edge(position: Vec3, normal: Vec3, lo: Vec3, size: f32) -> f32 {
+    let mut distances = (position - lo).min(lo + size - position);
+    distances[max_axis(normal.abs())] = INFINITY;
+    distances.min_element()
+}
+
+/// Display color of the primary ray in `debug_view`
+pub fn debug_color(
+    volume: &impl VoxelVolume,
+    camera: &Camera,
+    debug_view: DebugView,
+    ray: &Ray,
+) -> Vec3 {
+    let result = raytrace(volume, ray, Voxel::EMPTY);
+
+    if DebugView::Steps == debug_view {
+        return heatmap(result.steps as f32 / DEBUG_MAX_STEPS);
+    }
+
+    if !result.has_hit {
+        return Vec3::ZERO;
+    }
+
+    // voxel the ray hits and the one in front of the hit face
+    let voxel_pos = (result.position - 0.5 * result.normal).floor().as_ivec3();
+    let front_pos = (result.position + 0.5 * result.normal).floor().as_ivec3();
+
+    match debug_view {
+        DebugView::Off | DebugView::Steps => Vec3::ZERO,
+        DebugView::Normals => 0.5 * result.normal + 0.5,
+        // logarithmic to tell apart both near and far hits
+        DebugView::Depth => Vec3::splat(
+            1.0 - (1.0 + ray.origin.distance(result.position)).ln()
+                / (1.0 + camera.far - camera.near).ln(),
+        ),
+        DebugView::VoxelCoords => {
+            (voxel_pos & (Chunk::SIZE as i32 - 1)).as_vec3() / (Chunk::SIZE - 1) as f32
+        }
+        DebugView::Boundaries => {
+            let chunk_size = Chunk::SIZE as i32;
+            let chunk_lo = (chunk_size * voxel_pos.div_euclid(IVec3::splat(chunk_size))).as_vec3();
+
+            if distance_to_box_edge(result.position, result.normal, chunk_lo, chunk_size as f32)
+                < DEBUG_LINE_WIDTH
+            {
+                return Vec3::X;
+            }
+
+            // the empty leaf the ray has come through before hitting the face
+            let (world_lo, world_hi) = volume.bounds();
+
+            if front_pos.cmpge(world_lo).all() && front_pos.cmplt(world_hi).all() {
+                let leaf = volume.find_leaf(front_pos);
+
+                if distance_to_box_edge(
+                    result.position,
+                    result.normal,
+                    leaf.lo.as_vec3(),
+                    leaf.size as f32,
+                ) < DEBUG_LINE_WIDTH
+                {
+                    return Vec3::Y;
+                }
+            }
+
+            // faces along different axes are shaded differently
+            Vec3::splat(0.3 + 0.2 * result.normal.abs().dot(Vec3::new(1.0, 2.0, 3.0)) / 3.0)
+        }
+    }
+}
+
+/// Color of a single pixel of the render texture as the compute shader's `main()` computes it
+pub fn shade_pixel(
+    volume: &impl VoxelVolume,
+    camera: &Camera,
+    shading: &Shading<'_>,
+    index: UVec2,
+    render_texture_size: UVec2,
+) -> Vec4 {
+    let mut rng = Rng::new(index.y * render_texture_size.x + index.x, 0);
+
+    let aspect_ratio = render_texture_size.x as f32 / render_texture_size.y as f32;
+    // sample pixel centers, texture rows go from top to bottom
+    let mut screen_coord = 2.0 * (index.as_vec2() + 0.5) / render_texture_size.as_vec2() - 1.0;
+    screen_coord.y = -screen_coord.y;
+
+    let (origin, direction) = camera.ray(screen_coord, aspect_ratio);
+    // start at the near plane and discard hits behind the far plane
+    let mut ray = Ray::new(origin + camera.near * direction, direction);
+
+    // debug colors are displayed without tonemapping
+    if DebugView::Off != shading.debug_view {
+        return srgb_to_linear(debug_color(volume, camera, shading.debug_view, &ray)).extend(1.0);
+    }
+
+    // transparent surfaces are blended front to back
+    let mut color = Vec4::ZERO;
+    let mut medium = Voxel::EMPTY;
+    let mut ray_distance = 0.0;
+
+    for _ in 0..MAX_TRANSPARENT_LAYERS {
+        if color.w >= OPAQUE_ALPHA {
+            break;
+        }
+
+        let result = raytrace(volume, &ray, medium);
+        ray_distance += ray.origin.distance(result.position);
+
+        if !result.has_hit || ray_distance > camera.far - camera.near {
+            break;
+        }
+
+        if !result.voxel.is_empty() {
+            let material = material(shading.materials, result.voxel);
+            let opacity = voxel_opacity(shading.materials, &result);
+
+            let mut radiance = direct_lighting(volume, shading, result.position, result.normal)
+                + ambient_occlusion(
+                    volume,
+                    shading.ambient_occlusion,
+                    &mut rng,
+                    result.position,
+                    result.normal,
+                ) * ambient_radiance(&shading.ambient, result.normal);
+
+            // metals replace the diffuse lobe with the reflection
+            if material.metalness > 0.0 {
+                radiance = radiance.lerp(
+                    specular_radiance(
+                        volume,
+                        shading,
+                        result.position,
+                        result.normal,
+                        ray.direction,
+                        material.roughness,
+                        medium,
+                    ),
+                    material.metalness,
+                );
+            }
+
+            let surface = radiance * result.color.xyz() * material.albedo + material.emission;
+
+            color += ((1.0 - color.w) * opacity * surface).extend((1.0 - color.w) * opacity);
+        }
+
+        // rays starting inside a voxel have no surface to pass through
+        if Vec3::ZERO == result.normal {
+            break;
+        }
+
+        let direction = pass_surface(
+            shading.materials,
+            shading.refraction,
+            ray.direction,
+            result.normal,
+            &mut medium,
+            result.voxel,
+        );
+
+        ray = Ray::new(result.position + SURFACE_OFFSET * direction, direction);
+    }
+
+    // whatever the surfaces let through comes from the environment
+    (color.xyz() + (1.0 - color.w) * shading.environment.radiance(ray.direction)).extend(1.0)
+}
+
+/// Renders the whole frame on the CPU splitting rows between all available threads,
+/// tonemaps it with `2^exposure` scaled radiance and encodes it as sRGB
+pub fn render_image(
+    volume: &(impl VoxelVolume + Sync),
+    camera: &Camera,
+    shading: &Shading<'_>,
+    tonemapping: Tonemapping,
+    exposure: f32,
+    size: UVec2,
+) -> Image {
+    let bytes_per_row = (Image::BYTES_PER_PIXEL * size.x) as usize;
+    let mut pixels = vec![0; bytes_per_row * size.y as usize];
+
+    let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let rows_per_thread = (size.y as usize).div_ceil(n_threads).max(1);
+
+    std::thread::scope(|scope| {
+        for (band_index, band) in pixels
+            .chunks_mut(rows_per_thread * bytes_per_row)
+            .enumerate()
+        {
+            scope.spawn(move || {
+                for (row_offset, row) in band.chunks_exact_mut(bytes_per_row).enumerate() {
+                    let y = (band_index * rows_per_thread + row_offset) as u32;
+
+                    for (x, pixel) in row
+                        .chunks_exact_mut(Image::BYTES_PER_PIXEL as usize)
+                        .enumerate()
+                    {
+                        let color =
+                            shade_pixel(volume, camera, shading, UVec2::new(x as u32, y), size);
+                        let mapped = match shading.debug_view {
+                            DebugView::Off => tonemapping.apply(exposure.exp2() * color.xyz()),
+                            _ => color.xyz(),
+                        };
+                        let color = linear_to_srgb(mapped).extend(color.w);
+                        let color = (255.0 * color.clamp(Vec4::ZERO, Vec4::ONE)).round();
+
+                        pixel.copy_from_slice(&color.to_array().map(|channel| channel as u8));
+                    }
+                }
+            });
+        }
+    });
+
+    Image { size, pixels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::Color;
+    use crate::world::World;
+
+    const AXES: [Vec3; 6] = [
+        Vec3::X,
+        Vec3::NEG_X,
+        Vec3::Y,
+        Vec3::NEG_Y,
+        Vec3::Z,
+        Vec3::NEG_Z,
+    ];
+
+    fn hit(volume: &impl VoxelVolume, origin: Vec3, direction: Vec3) -> RaytraceResult {
+        raytrace(volume, &Ray::new(origin, direction), Voxel::EMPTY)
+    }
+
+    fn chunk_with(voxels: &[(UVec3, Voxel)]) -> Chunk {
+        let mut chunk = Chunk::all_same(Voxel::EMPTY);
+
+        for &(pos, voxel) in voxels {
+            chunk.set(pos, voxel);
+        }
+
+        chunk
+    }
+
+    /// Evenly spread unit vectors, `index` goes from 0 to `count - 1`
+    fn fibonacci_sphere(index: u32, count: u32) -> Vec3 {
+        let y = 1.0 - (2 * index + 1) as f32 / count as f32;
+        let azimuth = std::f32::consts::PI * (3.0 - 5.0_f32.sqrt()) * index as f32;
+        let radius = (1.0 - y * y).sqrt();
+
+        Vec3::new(radius * azimuth.cos(), y, radius * azimuth.sin())
+    }
+
+    /// Checks that a hit lies on a face between an empty voxel and `result.voxel`
+    fn assert_hit_on_face(get: impl Fn(IVec3) -> Voxel, result: &RaytraceResult) {
+        assert!(result.has_hit);
+        assert_ne!(Vec3::ZERO, result.normal);
+
+        let axis = max_axis(result.normal.abs());
+        let face = result.position[axis];
+        assert!((face - face.round()).abs() < 1.0e-4, "{result:?}");
+
+        let inside = (result.position - 0.5 * result.normal).floor().as_ivec3();
+        let outside = (result.position + 0.5 * result.normal).floor().as_ivec3();
+
+        assert_eq!(get(inside), result.voxel, "{result:?}");
+        assert!(!result.voxel.is_empty(), "{result:?}");
+        assert!(get(outside).is_empty(), "{result:?}");
+    }
+
+    #[test]
+    fn ray_aabb_axis_aligned() {
+        let ray = Ray::new(Vec3::new(-5.0, 0.5, 0.5), Vec3::X);
+        let hit = ray_aabb_intersect(Vec3::ZERO, Vec3::ONE, &ray);
+
+        assert!(hit.has_hit);
+        assert_eq!(5.0, hit.distance_near);
+        assert_eq!(6.0, hit.distance_far);
+
+        // parallel to the box but next to it
+        let ray = Ray::new(Vec3::new(-5.0, 1.5, 0.5), Vec3::X);
+        assert!(!ray_aabb_intersect(Vec3::ZERO, Vec3::ONE, &ray).has_hit);
+
+        // the box is behind the ray
+        let ray = Ray::new(Vec3::new(-5.0, 0.5, 0.5), Vec3::NEG_X);
+        assert!(!ray_aabb_intersect(Vec3::ZERO, Vec3::ONE, &ray).has_hit);
+    }
+
+    #[test]
+    fn ray_aabb_origin_inside() {
+        let ray = Ray::new(Vec3::splat(0.25), Vec3::NEG_Y);
+        let hit = ray_aabb_intersect(Vec3::ZERO, Vec3::ONE, &ray);
+
+        assert!(hit.has_hit);
+        assert_eq!(-0.75, hit.distance_near);
+        assert_eq!(0.25, hit.distance_far);
+    }
+
+    #[test]
+    fn ray_aabb_grazing() {
+        let hits = |origin: Vec3| {
+            ray_aabb_intersect(Vec3::ZERO, Vec3::ONE, &Ray::new(origin, Vec3::X)).has_hit
+        };
+
+        // boxes include their lower faces and exclude the upper ones like voxels do
+        assert!(hits(Vec3::new(-5.0, 0.0, 0.5)));
+        assert!(hits(Vec3::new(-5.0, 0.0, 0.0)));
+        assert!(!hits(Vec3::new(-5.0, 1.0, 0.5)));
+        assert!(!hits(Vec3::new(-5.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn miss_keeps_origin() {
+        let chunk = Chunk::new_sphere();
+        let origin = Vec3::new(-5.0, 8.0, 8.0);
+        let result = hit(&chunk, origin, Vec3::NEG_X);
+
+        assert!(!result.has_hit);
+        assert_eq!(origin, result.position);
+    }
+
+    #[test]
+    fn axis_aligned_rays() {
+        let voxel = Voxel::new(Color::RED, 1);
+        let chunk = chunk_with(&[(UVec3::splat(8), voxel)]);
+        let octree = Octree::from_chunk(&chunk);
+        let flat = {
+            let mut world = World::new();
+            world.insert_chunk(IVec3::ZERO, chunk.clone());
+            world.flatten()
+        };
+
+        let center = Vec3::splat(8.5);
+
+        for direction in AXES {
+            let origin = center - 20.0 * direction;
+            let expected_position = center - 0.5 * direction;
+
+            for result in [
+                hit(&chunk, origin, direction),
+                hit(&octree, origin, direction),
+                hit(&flat, origin, direction),
+            ] {
+                assert!(result.has_hit, "{direction}");
+                assert_eq!(voxel, result.voxel);
+                assert_eq!(-direction, result.normal);
+                assert!(
+                    expected_position.distance(result.position) < 1.0e-4,
+                    "{direction}"
+                );
+            }
+
+            // the same ray next to the voxel goes through the whole volume
+            let result = hit(&octree, origin + direction.yzx(), direction);
+            assert!(!result.has_hit, "{direction}");
+        }
+    }
+
+    #[test]
+    fn origin_inside_volume() {
+        let voxel = Voxel::new(Color::GREEN, 0);
+        let chunk = chunk_with(&[(UVec3::new(3, 8, 8), voxel)]);
+        let octree = Octree::from_chunk(&chunk);
+
+        let result = hit(&octree, Vec3::new(10.5, 8.5, 8.5), Vec3::NEG_X);
+
+        assert!(result.has_hit);
+        assert_eq!(Vec3::X, result.normal);
+        assert!(Vec3::new(4.0, 8.5, 8.5).distance(result.position) < 1.0e-4);
+
+        // rays starting inside a voxel have no face to hit
+        let origin = Vec3::new(3.25, 8.5, 8.5);
+        let result = hit(&octree, origin, Vec3::X);
+
+        assert!(result.has_hit);
+        assert_eq!(voxel, result.voxel);
+        assert_eq!(Vec3::ZERO, result.normal);
+        assert_eq!(origin, result.position);
+    }
+
+    #[test]
+    fn grazing_rays_along_faces_and_edges() {
+        let voxel = Voxel::from(Color::BLUE);
+        let chunk = chunk_with(&[
+            (UVec3::new(8, 0, 0), voxel),
+            (UVec3::new(8, 4, 4), voxel),
+            (UVec3::new(8, 15, 15), voxel),
+        ]);
+        let octree = Octree::from_chunk(&chunk);
+
+        let cases = [
+            // a ray in the plane between two voxel layers belongs to the upper one
+            (Vec3::new(-5.0, 4.0, 4.5), Some(Vec3::new(8.0, 4.0, 4.5))),
+            (Vec3::new(-5.0, 5.0, 4.5), None),
+            (Vec3::new(-5.0, 4.0, 4.0), Some(Vec3::new(8.0, 4.0, 4.0))),
+            (Vec3::new(-5.0, 5.0, 5.0), None),
+            // on the faces and edges of the volume bounds
+            (Vec3::new(-5.0, 0.0, 0.5), Some(Vec3::new(8.0, 0.0, 0.5))),
+            (Vec3::new(-5.0, 0.0, 0.0), Some(Vec3::new(8.0, 0.0, 0.0))),
+            (Vec3::new(-5.0, 16.0, 15.5), None),
+            (Vec3::new(-5.0, 15.5, 16.0), None),
+            (Vec3::new(-5.0, 16.0, 16.0), None),
+        ];
+
+        for (origin, expected_position) in cases {
+            for result in [hit(&chunk, origin, Vec3::X), hit(&octree, origin, Vec3::X)] {
+                assert_eq!(expected_position.is_some(), result.has_hit, "{origin}");
+
+                if let Some(position) = expected_position {
+                    assert_eq!(position, result.position, "{origin}");
+                    assert_eq!(Vec3::NEG_X, result.normal, "{origin}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crossing_chunk_borders() {
+        let voxel = Voxel::new(Color::RED, 2);
+        let mut world = World::new();
+        world.set(IVec3::new(-20, 3, 5), Color::GREEN.into());
+        world.set(IVec3::new(40, 3, -20), voxel);
+
+        let flat = world.flatten();
+
+        // goes through missing chunks before reaching the voxel
+        let result = hit(&flat, Vec3::new(-30.0, 3.5, -19.5), Vec3::X);
+
+        assert!(result.has_hit);
+        assert_eq!(voxel, result.voxel);
+        assert_eq!(Vec3::NEG_X, result.normal);
+        assert!(Vec3::new(40.0, 3.5, -19.5).distance(result.position) < 1.0e-4);
+
+        // diagonal rays step through chunks along all axes
+        let target = Vec3::new(40.5, 3.5, -19.5);
+        let origin = target + Vec3::new(-70.0, 40.0, 50.0);
+        let result = hit(&flat, origin, (target - origin).normalize());
+
+        assert_hit_on_face(|pos| world.get(pos), &result);
+        assert_eq!(voxel, result.voxel);
+    }
+
+    #[test]
+    fn hits_agree_with_voxels() {
+        const N_RAYS: u32 = 256;
+
+        let chunk = Chunk::new_sphere();
+        let octree = Octree::from_chunk(&chunk);
+        let world = World::new_sphere(20.0);
+        let flat = world.flatten();
+
+        let chunk_get = |pos: IVec3| {
+            let (lo, hi) = chunk.bounds();
+
+            match pos.cmpge(lo).all() && pos.cmplt(hi).all() {
+                true => chunk.get(pos.as_uvec3()),
+                false => Voxel::EMPTY,
+            }
+        };
+
+        let mut n_chunk_hits = 0;
+        let mut n_world_hits = 0;
+
+        for index in 0..N_RAYS {
+            // rays from all around aim at points spread over the volumes
+            let from = fibonacci_sphere(index, N_RAYS);
+            let to = fibonacci_sphere(7 * index % N_RAYS, N_RAYS) * (index % 8) as f32 / 8.0;
+
+            let origin = Vec3::splat(8.0) + 30.0 * from;
+            let direction = (Vec3::splat(8.0) + 8.0 * to - origin).normalize();
+
+            let chunk_result = hit(&chunk, origin, direction);
+            let octree_result = hit(&octree, origin, direction);
+
+            assert_eq!(chunk_result.has_hit, octree_result.has_hit);
+
+            if chunk_result.has_hit {
+                n_chunk_hits += 1;
+                assert_hit_on_face(chunk_get, &chunk_result);
+                assert_hit_on_face(|pos| octree.get(pos.as_uvec3()), &octree_result);
+                assert!(chunk_result.position.distance(octree_result.position) < 1.0e-3);
+            }
+
+            let world_origin = 50.0 * from;
+            let world_result = hit(&flat, world_origin, (20.0 * to - world_origin).normalize());
+
+            if world_result.has_hit {
+                n_world_hits += 1;
+                assert_hit_on_face(|pos| world.get(pos), &world_result);
+            }
+        }
+
+        // most rays aim at the spheres
+        assert!(n_chunk_hits > N_RAYS / 4, "{n_chunk_hits} hits");
+        assert!(n_world_hits > N_RAYS / 4, "{n_world_hits} hits");
+    }
+}
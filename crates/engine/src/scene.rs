@@ -0,0 +1,498 @@
+use crate::camera::{Camera, Projection};
+use crate::dynamic_resolution::DynamicResolution;
+use crate::environment::{Environment, EnvironmentMap};
+use crate::light::Light;
+use crate::material::Material;
+use crate::shading::{AmbientOcclusion, DebugView, RenderMode};
+use crate::tonemapping::Tonemapping;
+use crate::util::default;
+use crate::vox::VoxError;
+use crate::voxels_demo::VoxelsDemo;
+use crate::world::World;
+use glam::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SceneError {
+    #[error("failed to read scene file '{}': {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to parse scene file '{}': {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: ron::error::SpannedError,
+    },
+    #[error("invalid scene file '{}': {message}", path.display())]
+    Invalid { path: PathBuf, message: String },
+    #[error("failed to load model '{}': {source}", path.display())]
+    Vox { path: PathBuf, source: VoxError },
+    #[error("failed to load environment map '{}': {source}", path.display())]
+    EnvironmentMap {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+/// Scene description as written in a RON scene file, every field is optional
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneConfig {
+    pub window_size: UVec2,
+    /// Render texture size relative to the window size
+    pub render_scale: f32,
+    /// Adjust `render_scale` automatically to hold `target_fps`
+    pub dynamic_resolution: bool,
+    pub target_fps: f32,
+    pub render_mode: RenderMode,
+    /// Indirect bounces per path when path tracing
+    pub max_bounces: u32,
+    /// Samples per pixel for headless path traced renders
+    pub samples: u32,
+    /// Frames per simulated second of recordings
+    pub recording_fps: f32,
+    /// Seconds per full turn of the orbit camera while recording, zero leaves it to the mouse
+    pub turntable_period: f32,
+    /// Trace shadow rays towards the lights
+    pub shadows: bool,
+    /// Bend rays passing through transparent voxels by the index of refraction
+    pub refraction: bool,
+    pub ambient_occlusion: AmbientOcclusion,
+    pub tonemapping: Tonemapping,
+    /// Scales the radiance by `2^exposure` before tonemapping
+    pub exposure: f32,
+    /// Shows the traversal instead of the shaded scene
+    pub debug_view: DebugView,
+    /// Log GPU pass times measured with timestamp queries
+    pub gpu_profiling: bool,
+    pub camera: CameraConfig,
+    pub environment: EnvironmentConfig,
+    pub lights: Vec<Light>,
+    /// Material table indexed by `material` of the models, missing entries are default materials
+    pub materials: Vec<Material>,
+    pub models: Vec<ModelConfig>,
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        Self {
+            window_size: UVec2::new(1280, 720),
+            render_scale: 1.0,
+            dynamic_resolution: false,
+            target_fps: 60.0,
+            render_mode: RenderMode::Raytrace,
+            max_bounces: 4,
+            samples: 256,
+            recording_fps: 30.0,
+            turntable_period: 0.0,
+            shadows: true,
+            refraction: true,
+            ambient_occlusion: AmbientOcclusion::Neighbors,
+            tonemapping: Tonemapping::Aces,
+            exposure: 0.0,
+            debug_view: DebugView::Off,
+            gpu_profiling: false,
+            camera: default(),
+            environment: default(),
+            lights: vec![Light::default()],
+            materials: vec![],
+            models: vec![ModelConfig::Sphere {
+                radius: 12.0,
+                offset: IVec3::ZERO,
+                material: None,
+            }],
+        }
+    }
+}
+
+impl SceneConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_size.cmpeq(UVec2::ZERO).any() {
+            return Err(format!("window size {} has a zero side", self.window_size));
+        }
+
+        if !(self.recording_fps > 0.0 && self.recording_fps.is_finite()) {
+            return Err(format!(
+                "recording fps is {}, expected to be positive",
+                self.recording_fps
+            ));
+        }
+
+        if !(self.turntable_period >= 0.0 && self.turntable_period.is_finite()) {
+            return Err(format!(
+                "turntable period is {}, expected to be non-negative",
+                self.turntable_period
+            ));
+        }
+
+        if !self.exposure.is_finite() {
+            return Err(format!(
+                "exposure is {}, expected to be finite",
+                self.exposure
+            ));
+        }
+
+        let scale_range = VoxelsDemo::MIN_RENDER_SCALE..=VoxelsDemo::MAX_RENDER_SCALE;
+
+        if !scale_range.contains(&self.render_scale) {
+            return Err(format!(
+                "render scale is {}, expected to be in {scale_range:?}",
+                self.render_scale
+            ));
+        }
+
+        if !(self.target_fps > 0.0 && self.target_fps.is_finite()) {
+            return Err(format!(
+                "target fps is {}, expected to be positive",
+                self.target_fps
+            ));
+        }
+
+        if 0 == self.samples {
+            return Err("samples count is zero".to_owned());
+        }
+
+        for (index, light) in self.lights.iter().enumerate() {
+            let color = match light {
+                Light::Point { position, color } => {
+                    if !position.is_finite() {
+                        return Err(format!(
+                            "light {index}: point light position is {position}, \
+                            expected to be finite"
+                        ));
+                    }
+
+                    color
+                }
+                Light::Directional { direction, color } => {
+                    // zero, non-finite and denormal directions cannot be normalized
+                    if direction.try_normalize().is_none() {
+                        return Err(format!(
+                            "light {index}: directional light direction is {direction}, \
+                            expected to be finite and non-zero"
+                        ));
+                    }
+
+                    color
+                }
+            };
+
+            // the color scales the intensity as well
+            if !color.is_finite() {
+                return Err(format!(
+                    "light {index}: light color is {color}, expected to be finite"
+                ));
+            }
+        }
+
+        if self.materials.len() > Material::MAX_COUNT {
+            return Err(format!(
+                "scene has {} materials, expected at most {}",
+                self.materials.len(),
+                Material::MAX_COUNT
+            ));
+        }
+
+        if let EnvironmentConfig::Map { intensity, .. } = self.environment {
+            if intensity < 0.0 {
+                return Err(format!(
+                    "environment map intensity is {intensity}, expected to be non-negative"
+                ));
+            }
+        }
+
+        for (index, material) in self.materials.iter().enumerate() {
+            material
+                .validate()
+                .map_err(|message| format!("material {index}: {message}"))?;
+        }
+
+        let camera = &self.camera;
+
+        if camera.position == camera.target {
+            return Err("camera position and target are the same".to_owned());
+        }
+
+        if !(0.0 < camera.vfov && camera.vfov < 180.0) {
+            return Err(format!(
+                "camera vfov is {} degrees, expected to be in (0, 180)",
+                camera.vfov
+            ));
+        }
+
+        if let Projection::Orthographic { height } = camera.projection {
+            if !(height > 0.0 && height.is_finite()) {
+                return Err(format!(
+                    "orthographic camera height is {height}, expected to be positive"
+                ));
+            }
+        }
+
+        if !(0.0 < camera.near && camera.near < camera.far) {
+            return Err(format!(
+                "camera clip planes {}..{} are expected to satisfy 0 < near < far",
+                camera.near, camera.far
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Camera placed at `position` looking at `target`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    pub position: Vec3,
+    pub target: Vec3,
+    /// Vertical field of view in degrees
+    pub vfov: f32,
+    pub near: f32,
+    pub far: f32,
+    pub projection: Projection,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        let camera = Camera::default();
+
+        Self {
+            position: camera.position,
+            target: Vec3::ZERO,
+            vfov: camera.vfov.to_degrees(),
+            near: camera.near,
+            far: camera.far,
+            projection: camera.projection,
+        }
+    }
+}
+
+impl CameraConfig {
+    pub fn to_camera(&self) -> Camera {
+        Camera {
+            vfov: self.vfov.to_radians(),
+            near: self.near,
+            far: self.far,
+            projection: self.projection,
+            ..Camera::look_at(self.position, self.target)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum EnvironmentConfig {
+    Uniform(Vec3),
+    /// Sky fading from `horizon` up to `zenith` and down to `ground`
+    Gradient {
+        zenith: Vec3,
+        horizon: Vec3,
+        ground: Vec3,
+    },
+    /// Equirectangular Radiance `.hdr` map, `path` is relative to the scene file
+    Map {
+        path: PathBuf,
+        #[serde(default = "EnvironmentConfig::default_intensity")]
+        intensity: f32,
+    },
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        Self::Gradient {
+            zenith: Vec3::new(0.05, 0.17, 0.6),
+            horizon: Vec3::new(0.45, 0.6, 0.8),
+            ground: Vec3::new(0.07, 0.06, 0.05),
+        }
+    }
+}
+
+impl EnvironmentConfig {
+    fn default_intensity() -> f32 {
+        1.0
+    }
+}
+
+/// Model placed at `offset`, `material` overrides the material of all its voxels,
+/// otherwise `.vox` models bring their own materials, see [`crate::vox::VoxFile::to_world`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ModelConfig {
+    /// MagicaVoxel model, `path` is relative to the scene file
+    Vox {
+        path: PathBuf,
+        #[serde(default)]
+        offset: IVec3,
+        #[serde(default)]
+        material: Option<u8>,
+    },
+    Sphere {
+        radius: f32,
+        #[serde(default)]
+        offset: IVec3,
+        #[serde(default)]
+        material: Option<u8>,
+    },
+}
+
+/// Scene with all models loaded into one [`World`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub window_size: UVec2,
+    pub render_scale: f32,
+    pub dynamic_resolution: DynamicResolution,
+    pub render_mode: RenderMode,
+    pub max_bounces: u32,
+    pub samples: u32,
+    pub recording_fps: f32,
+    pub turntable_period: f32,
+    pub shadows: bool,
+    pub refraction: bool,
+    pub ambient_occlusion: AmbientOcclusion,
+    pub tonemapping: Tonemapping,
+    pub exposure: f32,
+    pub debug_view: DebugView,
+    pub gpu_profiling: bool,
+    pub camera: Camera,
+    /// Point the camera initially looks at
+    pub camera_target: Vec3,
+    pub environment: Environment,
+    pub lights: Vec<Light>,
+    pub materials: Vec<Material>,
+    pub world: World,
+}
+
+impl Scene {
+    /// Scene embedded into the binary, used unless another one is passed with `--scene <path>`
+    pub const DEFAULT_SOURCE: &'static str = include_str!("../assets/scenes/default.ron");
+    /// Stands for the path of [`Scene::DEFAULT_SOURCE`] in errors
+    pub const DEFAULT_NAME: &'static str = "default.ron";
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+
+        let source = std::fs::read_to_string(path).map_err(|source| SceneError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        Self::parse(&source, path, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    /// Parses [`Scene::DEFAULT_SOURCE`] resolving model paths relative to the working directory
+    pub fn load_default() -> Result<Self, SceneError> {
+        Self::parse(
+            Self::DEFAULT_SOURCE,
+            Path::new(Self::DEFAULT_NAME),
+            Path::new("."),
+        )
+    }
+
+    /// `path` is only used in errors
+    fn parse(source: &str, path: &Path, base_dir: &Path) -> Result<Self, SceneError> {
+        // optional fields are written without `Some(...)`
+        let config = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str::<SceneConfig>(source)
+            .map_err(|source| SceneError::Parse {
+                path: path.to_owned(),
+                source,
+            })?;
+
+        config.validate().map_err(|message| SceneError::Invalid {
+            path: path.to_owned(),
+            message,
+        })?;
+
+        Self::from_config(&config, base_dir)
+    }
+
+    /// Builds the scene resolving model paths relative to `base_dir`,
+    /// `config` is expected to be validated
+    pub fn from_config(config: &SceneConfig, base_dir: &Path) -> Result<Self, SceneError> {
+        let mut world = World::new();
+        let mut materials = config.materials.clone();
+
+        for model in &config.models {
+            let (mut model_world, offset, material) = match model {
+                ModelConfig::Vox {
+                    path,
+                    offset,
+                    material,
+                } => {
+                    let path = base_dir.join(path);
+
+                    // materials of the file are only added if not overridden
+                    let model = match material {
+                        Some(_) => World::load_vox(&path, &mut vec![]),
+                        None => World::load_vox(&path, &mut materials),
+                    }
+                    .map_err(|source| SceneError::Vox { path, source })?;
+
+                    (model, offset, material)
+                }
+                ModelConfig::Sphere {
+                    radius,
+                    offset,
+                    material,
+                } => (World::new_sphere(*radius), offset, material),
+            };
+
+            if let Some(material) = *material {
+                model_world.set_material(material);
+            }
+
+            world.paste(&model_world, *offset);
+        }
+
+        let environment = match &config.environment {
+            EnvironmentConfig::Uniform(color) => Environment::Uniform(*color),
+            EnvironmentConfig::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => Environment::Gradient {
+                zenith: *zenith,
+                horizon: *horizon,
+                ground: *ground,
+            },
+            EnvironmentConfig::Map { path, intensity } => {
+                let path = base_dir.join(path);
+                let map = EnvironmentMap::load(&path, *intensity)
+                    .map_err(|source| SceneError::EnvironmentMap { path, source })?;
+
+                Environment::Map(map)
+            }
+        };
+
+        Ok(Self {
+            window_size: config.window_size,
+            render_scale: config.render_scale,
+            dynamic_resolution: DynamicResolution {
+                enabled: config.dynamic_resolution,
+                ..DynamicResolution::new(config.target_fps)
+            },
+            render_mode: config.render_mode,
+            max_bounces: config.max_bounces,
+            samples: config.samples,
+            recording_fps: config.recording_fps,
+            turntable_period: config.turntable_period,
+            shadows: config.shadows,
+            refraction: config.refraction,
+            ambient_occlusion: config.ambient_occlusion,
+            tonemapping: config.tonemapping,
+            exposure: config.exposure,
+            debug_view: config.debug_view,
+            gpu_profiling: config.gpu_profiling,
+            camera: config.camera.to_camera(),
+            camera_target: config.camera.target,
+            environment,
+            lights: config.lights.clone(),
+            materials,
+            world,
+        })
+    }
+}
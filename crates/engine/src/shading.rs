@@ -0,0 +1,66 @@
+//! Shading options shared by the GPU renderer and its CPU port in [`crate::raytrace`]
+
+use serde::{Deserialize, Serialize};
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RenderMode {
+    /// Direct lighting only, one sample per pixel
+    #[default]
+    Raytrace = 0,
+    /// Multiple bounces with samples accumulated over frames while nothing changes
+    PathTrace = 1,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AmbientOcclusion {
+    Off = 0,
+    /// Darkens face corners next to solid voxels
+    #[default]
+    Neighbors = 1,
+    /// Short hemisphere rays from the hit point, noisy but catches larger cavities
+    Traced = 2,
+}
+
+impl AmbientOcclusion {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Neighbors,
+            Self::Neighbors => Self::Traced,
+            Self::Traced => Self::Off,
+        }
+    }
+}
+
+/// Shows what the traversal of the primary rays does instead of the shaded scene,
+/// debug colors are displayed as is without tonemapping
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DebugView {
+    #[default]
+    Off = 0,
+    /// Hit face normals mapped from `[-1, 1]` to `[0, 1]`
+    Normals = 1,
+    /// Hit distance on a logarithmic scale from white at the camera to black at the far plane
+    Depth = 2,
+    /// Heatmap of the leaves visited by the traversal from blue for none to red for 64 or more
+    Steps = 3,
+    /// Coordinates of the hit voxel within its chunk as RGB
+    VoxelCoords = 4,
+    /// Chunk edges in red and edges of the octree leaves in front of the hit faces in green
+    Boundaries = 5,
+}
+
+impl DebugView {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Normals,
+            Self::Normals => Self::Depth,
+            Self::Depth => Self::Steps,
+            Self::Steps => Self::VoxelCoords,
+            Self::VoxelCoords => Self::Boundaries,
+            Self::Boundaries => Self::Off,
+        }
+    }
+}
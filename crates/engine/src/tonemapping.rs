@@ -0,0 +1,105 @@
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+/// Maps unbounded linear radiance into the displayable `[0, 1]` range,
+/// same as `tonemap()` in `screen-quad-fragment.glsl`
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Tonemapping {
+    /// `c / (1 + c)` per channel, keeps dark tones intact but washes out bright colors
+    Reinhard = 0,
+    /// Narkowicz fit of the ACES filmic curve, contrasty with saturated highlights
+    #[default]
+    Aces = 1,
+    /// Minimal AgX, desaturates bright colors towards white instead of clipping them
+    AgX = 2,
+}
+
+impl Tonemapping {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Reinhard => Self::Aces,
+            Self::Aces => Self::AgX,
+            Self::AgX => Self::Reinhard,
+        }
+    }
+
+    /// Tonemaps linear `color` and returns it still linear
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO);
+
+        match self {
+            Self::Reinhard => color / (1.0 + color),
+            Self::Aces => {
+                let numerator = color * (2.51 * color + 0.03);
+                let denominator = color * (2.43 * color + 0.59) + 0.14;
+                (numerator / denominator).clamp(Vec3::ZERO, Vec3::ONE)
+            }
+            Self::AgX => agx(color),
+        }
+    }
+}
+
+/// See <https://iolite-engine.com/blog_posts/minimal_agx_implementation>
+fn agx(color: Vec3) -> Vec3 {
+    const INSET: Mat3 = Mat3::from_cols_array(&[
+        0.84247905,
+        0.042328242,
+        0.042375654,
+        0.0784336,
+        0.87846863,
+        0.0784336,
+        0.079223745,
+        0.07916613,
+        0.879143,
+    ]);
+    const OUTSET: Mat3 = Mat3::from_cols_array(&[
+        1.196879,
+        -0.052896854,
+        -0.052971635,
+        -0.09802088,
+        1.1519032,
+        -0.09804345,
+        -0.09902974,
+        -0.098961174,
+        1.1510737,
+    ]);
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let log = (INSET * color).max(Vec3::splat(1.0e-10)).map(f32::log2);
+    let x = (log.clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV)) - MIN_EV) / (MAX_EV - MIN_EV);
+
+    // polynomial fit of the AgX base contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+
+    // the curve outputs display values with a 2.2 gamma
+    (OUTSET * curve)
+        .clamp(Vec3::ZERO, Vec3::ONE)
+        .map(|channel| channel.powf(2.2))
+}
+
+/// Exact sRGB transfer function as in `*Srgb` texture formats
+pub fn linear_to_srgb(color: Vec3) -> Vec3 {
+    color.map(|channel| {
+        if channel <= 0.0031308 {
+            12.92 * channel
+        } else {
+            1.055 * channel.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    color.map(|channel| {
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
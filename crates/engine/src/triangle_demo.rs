@@ -0,0 +1,201 @@
+use crate::context::RenderContext;
+use crate::util::default;
+use bytemuck::{Pod, Zeroable};
+use glam::*;
+use std::borrow::Cow;
+use tracing::error;
+use wgpu::util::DeviceExt as _;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+struct Vertex {
+    position: Vec2,
+    color: Vec3,
+}
+
+pub struct TriangleDemo {
+    pub context: RenderContext,
+    pub vertex_buffer: wgpu::Buffer,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub vertex_shader: wgpu::ShaderModule,
+    pub fragment_shader: wgpu::ShaderModule,
+    /// Created for the surface format and re-created whenever it changes
+    pub pipeline: Option<(wgpu::TextureFormat, wgpu::RenderPipeline)>,
+}
+
+impl TriangleDemo {
+    const N_VERTICES: u32 = 3;
+
+    pub fn new(context: RenderContext) -> Self {
+        let vertices = [
+            Vertex {
+                position: Vec2::new(-0.5, -f32::sqrt(3.0) / 6.0),
+                color: Vec3::X,
+            },
+            Vertex {
+                position: Vec2::new(0.5, -f32::sqrt(3.0) / 6.0),
+                color: Vec3::Y,
+            },
+            Vertex {
+                position: Vec2::new(0.0, f32::sqrt(3.0) / 3.0),
+                color: Vec3::Z,
+            },
+        ];
+
+        let vertex_buffer = context
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                contents: bytemuck::cast_slice(&vertices),
+                label: Some("rainbow-triangle"),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        let pipeline_layout =
+            context
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("rainbow-triangle"),
+                    bind_group_layouts: &[],
+                    push_constant_ranges: &[wgpu::PushConstantRange {
+                        stages: wgpu::ShaderStages::VERTEX,
+                        range: 0..std::mem::size_of::<UVec2>() as u32,
+                    }],
+                });
+
+        let vertex_shader = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("triangle-vertex"),
+                source: wgpu::ShaderSource::Glsl {
+                    shader: Cow::Borrowed(include_str!("../assets/shaders/triangle-vertex.glsl")),
+                    stage: wgpu::naga::ShaderStage::Vertex,
+                    defines: default(),
+                },
+            });
+
+        let fragment_shader = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("triangle-fragment"),
+                source: wgpu::ShaderSource::Glsl {
+                    shader: Cow::Borrowed(include_str!("../assets/shaders/triangle-fragment.glsl")),
+                    stage: wgpu::naga::ShaderStage::Fragment,
+                    defines: default(),
+                },
+            });
+
+        Self {
+            context,
+            vertex_buffer,
+            pipeline_layout,
+            vertex_shader,
+            fragment_shader,
+            pipeline: None,
+        }
+    }
+
+    fn create_pipeline(&self, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+        self.context
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("rainbow-triangle"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.vertex_shader,
+                    entry_point: Some("main"),
+                    compilation_options: default(),
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<Vertex>() as u64,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x3],
+                    }],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.fragment_shader,
+                    entry_point: Some("main"),
+                    compilation_options: default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                cache: None,
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+    }
+
+    pub fn draw(&mut self) {
+        let Some(surface) = self.context.surface.as_ref() else {
+            error!("no surface to draw to");
+            return;
+        };
+
+        let Ok(cur_texture) = surface.get_current_texture() else {
+            error!("no next swapchain texture");
+            return;
+        };
+
+        let viewport_size = {
+            let extent = cur_texture.texture.size();
+            UVec2::new(extent.width, extent.height)
+        };
+
+        let format = cur_texture.texture.format();
+
+        if self.pipeline.as_ref().map(|(cached, _)| *cached) != Some(format) {
+            self.pipeline = Some((format, self.create_pipeline(format)));
+        }
+
+        let Some((_, pipeline)) = self.pipeline.as_ref() else {
+            unreachable!();
+        };
+
+        let view = cur_texture.texture.create_view(&default());
+        let mut encoder = self.context.device.create_command_encoder(&default());
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("rainbow-triangle"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            pass.set_pipeline(pipeline);
+            pass.set_push_constants(
+                wgpu::ShaderStages::VERTEX,
+                0,
+                bytemuck::bytes_of(&viewport_size),
+            );
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            pass.draw(0..Self::N_VERTICES, 0..1);
+        }
+
+        self.context.queue.submit([encoder.finish()]);
+        cur_texture.present();
+    }
+}
@@ -0,0 +1,982 @@
+//! MagicaVoxel `.vox` loader, see
+//! <https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt>
+
+use crate::material::Material;
+use crate::octree::{Color, Voxel};
+use crate::util::default;
+use crate::world::World;
+use glam::*;
+use std::{collections::HashMap, io, path::Path, str::Utf8Error};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VoxError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("not a .vox file")]
+    InvalidMagic,
+    #[error("unexpected end of data")]
+    UnexpectedEof,
+    #[error("expected 'MAIN' chunk, found '{0}'")]
+    MissingMain(String),
+    #[error("chunk '{0}' has invalid size")]
+    InvalidChunkSize(String),
+    #[error("invalid element count {0}")]
+    InvalidCount(i32),
+    #[error("'XYZI' chunk is not preceded by a 'SIZE' chunk")]
+    MissingModelSize,
+    #[error("invalid model size {0}")]
+    InvalidModelSize(IVec3),
+    #[error("voxel {pos} is out of model bounds {size}")]
+    VoxelOutOfBounds { pos: UVec3, size: UVec3 },
+    #[error(transparent)]
+    InvalidString(#[from] Utf8Error),
+    #[error("invalid rotation {0:#09b}")]
+    InvalidRotation(u8),
+    #[error("invalid rotation '{0}'")]
+    InvalidRotationString(String),
+    #[error("invalid translation '{0}'")]
+    InvalidTranslation(String),
+    #[error("scene node {0} is missing")]
+    MissingNode(i32),
+    #[error("model {0} is missing")]
+    MissingModel(i32),
+    #[error("scene graph has a cycle through node {0}")]
+    NodeCycle(i32),
+    #[error("invalid material property '{name}' value '{value}'")]
+    InvalidMaterialProperty { name: String, value: String },
+    #[error("models use more than {} materials", Material::MAX_COUNT)]
+    TooManyMaterials,
+}
+
+/// Model voxels in MagicaVoxel coordinates (`+Z` is up) with palette indices
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxModel {
+    pub size: UVec3,
+    pub voxels: Vec<(UVec3, u8)>,
+}
+
+/// Signed permutation matrix stored by rows, the only rotations MagicaVoxel supports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxTransform {
+    pub rotation: [IVec3; 3],
+    pub translation: IVec3,
+}
+
+impl Default for VoxTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl VoxTransform {
+    pub const IDENTITY: Self = Self {
+        rotation: [IVec3::X, IVec3::Y, IVec3::Z],
+        translation: IVec3::ZERO,
+    };
+
+    /// Decodes the `_r` byte: bits 0-1 and 2-3 are the columns of the non-zero entries
+    /// of the first and second rows, bits 4-6 are the signs of the rows
+    pub fn decode_rotation(bits: u8) -> Result<[IVec3; 3], VoxError> {
+        let first = (bits & 0b11) as usize;
+        let second = ((bits >> 2) & 0b11) as usize;
+
+        if first >= 3 || second >= 3 || first == second {
+            return Err(VoxError::InvalidRotation(bits));
+        }
+
+        let columns = [first, second, 3 - first - second];
+
+        Ok(std::array::from_fn(|row| {
+            let sign = if bits & (0b10000 << row) != 0 { -1 } else { 1 };
+            let mut value = IVec3::ZERO;
+            value[columns[row]] = sign;
+            value
+        }))
+    }
+
+    pub fn rotate(&self, pos: IVec3) -> IVec3 {
+        IVec3::new(
+            self.rotation[0].dot(pos),
+            self.rotation[1].dot(pos),
+            self.rotation[2].dot(pos),
+        )
+    }
+
+    pub fn apply(&self, pos: IVec3) -> IVec3 {
+        self.rotate(pos) + self.translation
+    }
+
+    /// Transform applying `child` first and `self` second
+    pub fn then(&self, child: &Self) -> Self {
+        let rotation = std::array::from_fn(|row| {
+            let lhs = self.rotation[row];
+            lhs.x * child.rotation[0] + lhs.y * child.rotation[1] + lhs.z * child.rotation[2]
+        });
+
+        Self {
+            rotation,
+            translation: self.apply(child.translation),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoxNode {
+    Transform {
+        child: i32,
+        transform: VoxTransform,
+        hidden: bool,
+    },
+    Group {
+        children: Vec<i32>,
+    },
+    Shape {
+        models: Vec<i32>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxFile {
+    pub version: u32,
+    pub models: Vec<VoxModel>,
+    /// Colors indexed by voxel palette indices, index `0` is empty
+    pub palette: [Color; 256],
+    /// Scene graph rooted at node `0`, empty for files without one
+    pub nodes: HashMap<i32, VoxNode>,
+    /// `MATL` materials indexed by voxel palette indices, `None` for diffuse ones,
+    /// `emission` is relative to the palette color
+    pub materials: [Option<Material>; 256],
+}
+
+impl VoxFile {
+    pub const MAGIC: [u8; 4] = *b"VOX ";
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, VoxError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, VoxError> {
+        let mut reader = Reader(bytes);
+
+        if reader.bytes(4)? != Self::MAGIC {
+            return Err(VoxError::InvalidMagic);
+        }
+
+        let version = reader.u32()?;
+
+        let RawChunk { id, children, .. } = reader.chunk()?;
+
+        if id != *b"MAIN" {
+            return Err(VoxError::MissingMain(
+                String::from_utf8_lossy(&id).into_owned(),
+            ));
+        }
+
+        let mut file = Self {
+            version,
+            models: vec![],
+            palette: default_palette(),
+            nodes: HashMap::new(),
+            materials: [None; 256],
+        };
+
+        let mut reader = Reader(children);
+        let mut model_size = None;
+
+        while !reader.0.is_empty() {
+            let RawChunk { id, content, .. } = reader.chunk()?;
+            let mut content = Reader(content);
+
+            match &id {
+                b"SIZE" => {
+                    let size = content.ivec3()?;
+
+                    if size.cmple(IVec3::ZERO).any() {
+                        return Err(VoxError::InvalidModelSize(size));
+                    }
+
+                    model_size = Some(size.as_uvec3());
+                }
+                b"XYZI" => {
+                    let size = model_size.take().ok_or(VoxError::MissingModelSize)?;
+                    let count = content.count()?;
+                    let mut voxels = Vec::with_capacity(count.min(content.0.len() / 4));
+
+                    for _ in 0..count {
+                        let &[x, y, z, index] = content.bytes(4)? else {
+                            unreachable!();
+                        };
+
+                        let pos = UVec3::new(x as u32, y as u32, z as u32);
+
+                        if pos.cmpge(size).any() {
+                            return Err(VoxError::VoxelOutOfBounds { pos, size });
+                        }
+
+                        if index != 0 {
+                            voxels.push((pos, index));
+                        }
+                    }
+
+                    file.models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    for index in 1..file.palette.len() {
+                        let &[r, g, b, _] = content.bytes(4)? else {
+                            unreachable!();
+                        };
+
+                        file.palette[index] = Color::rgb8(r, g, b);
+                    }
+                }
+                b"MATL" => {
+                    let id = content.i32()?;
+                    let properties = content.dict()?;
+
+                    // material ids are palette indices, `0` is the empty voxel
+                    if let Ok(index @ 1..) = u8::try_from(id) {
+                        file.materials[index as usize] = parse_material(&properties)?;
+                    }
+                }
+                b"nTRN" => {
+                    let node_id = content.i32()?;
+                    let attributes = content.dict()?;
+                    let child = content.i32()?;
+                    let _reserved = content.i32()?;
+                    let _layer = content.i32()?;
+                    let n_frames = content.count()?;
+
+                    // animated transforms are not supported, only the first frame is used
+                    let mut transform = VoxTransform::IDENTITY;
+
+                    for frame in 0..n_frames {
+                        let frame_attributes = content.dict()?;
+
+                        if frame == 0 {
+                            transform = parse_frame(&frame_attributes)?;
+                        }
+                    }
+
+                    let hidden = attributes.get("_hidden").is_some_and(|value| value == "1");
+
+                    file.nodes.insert(
+                        node_id,
+                        VoxNode::Transform {
+                            child,
+                            transform,
+                            hidden,
+                        },
+                    );
+                }
+                b"nGRP" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let n_children = content.count()?;
+                    let children = (0..n_children)
+                        .map(|_| content.i32())
+                        .collect::<Result<_, _>>()?;
+
+                    file.nodes.insert(node_id, VoxNode::Group { children });
+                }
+                b"nSHP" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let n_models = content.count()?;
+                    let mut models = Vec::with_capacity(n_models.min(content.0.len()));
+
+                    for _ in 0..n_models {
+                        models.push(content.i32()?);
+                        let _model_attributes = content.dict()?;
+                    }
+
+                    file.nodes.insert(node_id, VoxNode::Shape { models });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Places all models into a [`World`] converting MagicaVoxel's `+Z` up to `+Y` up,
+    /// models are centered around their translation as in the editor.
+    /// Materials the voxels use are appended to `materials` unless it already has them
+    pub fn to_world(&self, materials: &mut Vec<Material>) -> Result<World, VoxError> {
+        let material_indices = self.material_indices(materials)?;
+        let mut world = World::new();
+
+        if self.nodes.is_empty() {
+            for model in &self.models {
+                self.place_model(
+                    &mut world,
+                    model,
+                    &VoxTransform::IDENTITY,
+                    &material_indices,
+                );
+            }
+        } else {
+            self.place_node(
+                &mut world,
+                0,
+                &VoxTransform::IDENTITY,
+                &material_indices,
+                &mut vec![],
+            )?;
+        }
+
+        Ok(world)
+    }
+
+    /// Indices into `materials` by palette index, voxels without a `MATL` material use material `0`
+    fn material_indices(&self, materials: &mut Vec<Material>) -> Result<[u8; 256], VoxError> {
+        if materials.is_empty() {
+            materials.push(Material::default());
+        }
+
+        let mut is_used = [false; 256];
+
+        for &(_, index) in self.models.iter().flat_map(|model| &model.voxels) {
+            is_used[index as usize] = true;
+        }
+
+        let mut indices = [0; 256];
+
+        for (index, material) in self.materials.iter().enumerate() {
+            let Some(material) = material.filter(|_| is_used[index]) else {
+                continue;
+            };
+
+            let material = Material {
+                emission: material.emission * self.palette[index].to_linear().xyz(),
+                ..material
+            };
+
+            let position = match materials.iter().position(|&other| other == material) {
+                Some(position) => position,
+                None if materials.len() < Material::MAX_COUNT => {
+                    materials.push(material);
+                    materials.len() - 1
+                }
+                None => return Err(VoxError::TooManyMaterials),
+            };
+
+            indices[index] = position as u8;
+        }
+
+        Ok(indices)
+    }
+
+    fn place_node(
+        &self,
+        world: &mut World,
+        node_id: i32,
+        transform: &VoxTransform,
+        material_indices: &[u8; 256],
+        path: &mut Vec<i32>,
+    ) -> Result<(), VoxError> {
+        if path.contains(&node_id) {
+            return Err(VoxError::NodeCycle(node_id));
+        }
+
+        let node = self
+            .nodes
+            .get(&node_id)
+            .ok_or(VoxError::MissingNode(node_id))?;
+
+        path.push(node_id);
+
+        match node {
+            VoxNode::Transform {
+                child,
+                transform: local,
+                hidden,
+            } => {
+                if !hidden {
+                    self.place_node(
+                        world,
+                        *child,
+                        &transform.then(local),
+                        material_indices,
+                        path,
+                    )?;
+                }
+            }
+            VoxNode::Group { children } => {
+                for &child in children {
+                    self.place_node(world, child, transform, material_indices, path)?;
+                }
+            }
+            VoxNode::Shape { models } => {
+                for &model_id in models {
+                    let model = usize::try_from(model_id)
+                        .ok()
+                        .and_then(|index| self.models.get(index))
+                        .ok_or(VoxError::MissingModel(model_id))?;
+
+                    self.place_model(world, model, transform, material_indices);
+                }
+            }
+        }
+
+        path.pop();
+
+        Ok(())
+    }
+
+    fn place_model(
+        &self,
+        world: &mut World,
+        model: &VoxModel,
+        transform: &VoxTransform,
+        material_indices: &[u8; 256],
+    ) {
+        let pivot = (model.size / 2).as_ivec3();
+
+        for &(pos, index) in &model.voxels {
+            let pos = transform.apply(pos.as_ivec3() - pivot);
+            world.set(
+                IVec3::new(pos.x, pos.z, -1 - pos.y),
+                Voxel::new(
+                    self.palette[index as usize],
+                    material_indices[index as usize],
+                ),
+            );
+        }
+    }
+}
+
+impl World {
+    /// See [`VoxFile::to_world`]
+    pub fn load_vox(
+        path: impl AsRef<Path>,
+        materials: &mut Vec<Material>,
+    ) -> Result<Self, VoxError> {
+        VoxFile::open(path)?.to_world(materials)
+    }
+}
+
+/// Palette MagicaVoxel uses for files without an `RGBA` chunk:
+/// a 6x6x6 color cube followed by red, green, blue and gray ramps
+pub fn default_palette() -> [Color; 256] {
+    const CUBE_STEPS: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP_STEPS: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut palette = [Color::TRANSPARENT_BLACK; 256];
+    let mut colors = palette.iter_mut().skip(1);
+
+    for r in CUBE_STEPS {
+        for g in CUBE_STEPS {
+            for b in CUBE_STEPS {
+                // black is left out of the cube
+                if r == 0 && g == 0 && b == 0 {
+                    continue;
+                }
+
+                if let Some(color) = colors.next() {
+                    *color = Color::rgb8(r, g, b);
+                }
+            }
+        }
+    }
+
+    for [r, g, b] in [[1, 0, 0], [0, 1, 0], [0, 0, 1], [1, 1, 1]] {
+        for (color, value) in colors.by_ref().zip(RAMP_STEPS) {
+            *color = Color::rgb8(r * value, g * value, b * value);
+        }
+    }
+
+    palette
+}
+
+fn parse_frame(attributes: &HashMap<String, String>) -> Result<VoxTransform, VoxError> {
+    let mut transform = VoxTransform::IDENTITY;
+
+    if let Some(rotation) = attributes.get("_r") {
+        let bits = rotation
+            .trim()
+            .parse()
+            .map_err(|_| VoxError::InvalidRotationString(rotation.clone()))?;
+
+        transform.rotation = VoxTransform::decode_rotation(bits)?;
+    }
+
+    if let Some(translation) = attributes.get("_t") {
+        let invalid = || VoxError::InvalidTranslation(translation.clone());
+
+        let components = translation
+            .split_whitespace()
+            .map(|value| value.parse::<i32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let &[x, y, z] = components.as_slice() else {
+            return Err(invalid());
+        };
+
+        transform.translation = IVec3::new(x, y, z);
+    }
+
+    Ok(transform)
+}
+
+/// Closest [`Material`] to a `MATL` material, `None` for diffuse, `_blend` and `_media` ones.
+/// Glass stores the index of refraction minus one, the emission power `_flux` is a power of two
+fn parse_material(properties: &HashMap<String, String>) -> Result<Option<Material>, VoxError> {
+    let property = |name: &str, default: f32| match properties.get(name) {
+        Some(value) => value
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| VoxError::InvalidMaterialProperty {
+                name: name.to_owned(),
+                value: value.clone(),
+            }),
+        None => Ok(default),
+    };
+
+    let weight = property("_weight", 1.0)?.clamp(0.0, 1.0);
+    let roughness = property("_rough", Material::default().roughness)?.clamp(0.0, 1.0);
+
+    let material = match properties.get("_type").map(String::as_str) {
+        Some("_metal") => Material {
+            roughness,
+            metalness: property("_metal", weight)?.clamp(0.0, 1.0),
+            ..default()
+        },
+        Some("_glass") => Material {
+            roughness,
+            opacity: 1.0 - property("_trans", weight)?.clamp(0.0, 1.0),
+            ior: 1.0 + property("_ior", 0.5)?.max(0.0),
+            ..default()
+        },
+        Some("_emit") => Material {
+            emission: Vec3::splat(
+                property("_emit", weight)?.max(0.0)
+                    * property("_flux", 0.0)?.clamp(0.0, 4.0).exp2(),
+            ),
+            ..default()
+        },
+        _ => return Ok(None),
+    };
+
+    Ok(Some(material))
+}
+
+/// Little-endian cursor over the file contents
+struct Reader<'b>(&'b [u8]);
+
+impl<'b> Reader<'b> {
+    fn bytes(&mut self, len: usize) -> Result<&'b [u8], VoxError> {
+        if self.0.len() < len {
+            return Err(VoxError::UnexpectedEof);
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+
+        Ok(head)
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        let bytes = self.bytes(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, VoxError> {
+        Ok(self.i32()? as u32)
+    }
+
+    fn ivec3(&mut self) -> Result<IVec3, VoxError> {
+        Ok(IVec3::new(self.i32()?, self.i32()?, self.i32()?))
+    }
+
+    fn count(&mut self) -> Result<usize, VoxError> {
+        let count = self.i32()?;
+        usize::try_from(count).map_err(|_| VoxError::InvalidCount(count))
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let len = self.count()?;
+        Ok(std::str::from_utf8(self.bytes(len)?)?.to_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, VoxError> {
+        let len = self.count()?;
+
+        (0..len)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    fn chunk(&mut self) -> Result<RawChunk<'b>, VoxError> {
+        let id: [u8; 4] = self.bytes(4)?.try_into().unwrap();
+        let invalid_size = || VoxError::InvalidChunkSize(String::from_utf8_lossy(&id).into_owned());
+
+        let content_len = usize::try_from(self.i32()?).map_err(|_| invalid_size())?;
+        let children_len = usize::try_from(self.i32()?).map_err(|_| invalid_size())?;
+
+        let content = self.bytes(content_len).map_err(|_| invalid_size())?;
+        let children = self.bytes(children_len).map_err(|_| invalid_size())?;
+
+        Ok(RawChunk {
+            id,
+            content,
+            children,
+        })
+    }
+}
+
+struct RawChunk<'b> {
+    id: [u8; 4],
+    content: &'b [u8],
+    children: &'b [u8],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((content.len() as i32).to_le_bytes());
+        bytes.extend((children.len() as i32).to_le_bytes());
+        bytes.extend(content);
+        bytes.extend(children);
+        bytes
+    }
+
+    fn file(children: &[u8]) -> Vec<u8> {
+        let mut bytes = VoxFile::MAGIC.to_vec();
+        bytes.extend(150_u32.to_le_bytes());
+        bytes.extend(chunk(b"MAIN", &[], children));
+        bytes
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = ints(&[entries.len() as i32]);
+
+        for string in entries.iter().flat_map(|&(key, value)| [key, value]) {
+            bytes.extend(ints(&[string.len() as i32]));
+            bytes.extend(string.as_bytes());
+        }
+
+        bytes
+    }
+
+    fn model(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut xyzi = ints(&[voxels.len() as i32]);
+        xyzi.extend(voxels.concat());
+
+        [
+            chunk(b"SIZE", &ints(&size), &[]),
+            chunk(b"XYZI", &xyzi, &[]),
+        ]
+        .concat()
+    }
+
+    fn transform(node_id: i32, child: i32, frame: &[(&str, &str)]) -> Vec<u8> {
+        let content = [
+            ints(&[node_id]),
+            dict(&[]),
+            ints(&[child, -1, 0, 1]),
+            dict(frame),
+        ]
+        .concat();
+
+        chunk(b"nTRN", &content, &[])
+    }
+
+    fn shape(node_id: i32, model_id: i32) -> Vec<u8> {
+        let content = [ints(&[node_id]), dict(&[]), ints(&[1, model_id]), dict(&[])].concat();
+        chunk(b"nSHP", &content, &[])
+    }
+
+    fn group(node_id: i32, children: &[i32]) -> Vec<u8> {
+        let content = [
+            ints(&[node_id]),
+            dict(&[]),
+            ints(&[children.len() as i32]),
+            ints(children),
+        ]
+        .concat();
+
+        chunk(b"nGRP", &content, &[])
+    }
+
+    #[test]
+    fn truncated_files() {
+        let bytes = file(&model([1, 1, 1], &[[0, 0, 0, 1]]));
+
+        assert!(matches!(
+            VoxFile::parse(&bytes[..6]),
+            Err(VoxError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            VoxFile::parse(&bytes[..bytes.len() - 1]),
+            Err(VoxError::InvalidChunkSize(id)) if "MAIN" == id
+        ));
+
+        // the model chunks claim more than the `MAIN` chunk holds
+        let mut children = model([1, 1, 1], &[[0, 0, 0, 1]]);
+        children.truncate(children.len() - 1);
+
+        assert!(matches!(
+            VoxFile::parse(&file(&children)),
+            Err(VoxError::InvalidChunkSize(id)) if "XYZI" == id
+        ));
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = file(&[]);
+        bytes[..4].copy_from_slice(b"XOV ");
+
+        assert!(matches!(
+            VoxFile::parse(&bytes),
+            Err(VoxError::InvalidMagic)
+        ));
+
+        let mut bytes = VoxFile::MAGIC.to_vec();
+        bytes.extend(150_u32.to_le_bytes());
+        bytes.extend(chunk(b"SIZE", &ints(&[1, 1, 1]), &[]));
+
+        assert!(matches!(
+            VoxFile::parse(&bytes),
+            Err(VoxError::MissingMain(id)) if "SIZE" == id
+        ));
+    }
+
+    #[test]
+    fn voxels_before_size() {
+        let xyzi = [ints(&[1]), vec![0, 0, 0, 1]].concat();
+
+        assert!(matches!(
+            VoxFile::parse(&file(&chunk(b"XYZI", &xyzi, &[]))),
+            Err(VoxError::MissingModelSize)
+        ));
+
+        // every `XYZI` chunk needs its own `SIZE` chunk
+        let children = [model([1, 1, 1], &[]), chunk(b"XYZI", &xyzi, &[])].concat();
+
+        assert!(matches!(
+            VoxFile::parse(&file(&children)),
+            Err(VoxError::MissingModelSize)
+        ));
+    }
+
+    #[test]
+    fn invalid_model_size() {
+        assert!(matches!(
+            VoxFile::parse(&file(&model([2, 0, 2], &[]))),
+            Err(VoxError::InvalidModelSize(size)) if IVec3::new(2, 0, 2) == size
+        ));
+    }
+
+    #[test]
+    fn voxel_out_of_bounds() {
+        assert!(matches!(
+            VoxFile::parse(&file(&model([2, 3, 4], &[[1, 3, 0, 1]]))),
+            Err(VoxError::VoxelOutOfBounds { pos, size })
+                if UVec3::new(1, 3, 0) == pos && UVec3::new(2, 3, 4) == size
+        ));
+    }
+
+    #[test]
+    fn node_cycle() {
+        let children = [
+            model([1, 1, 1], &[[0, 0, 0, 1]]),
+            transform(0, 1, &[]),
+            group(1, &[2]),
+            transform(2, 1, &[]),
+        ]
+        .concat();
+
+        let file = VoxFile::parse(&file(&children)).unwrap();
+
+        assert!(matches!(
+            file.to_world(&mut vec![]),
+            Err(VoxError::NodeCycle(1))
+        ));
+    }
+
+    #[test]
+    fn dangling_nodes() {
+        let missing_node = [model([1, 1, 1], &[]), transform(0, 5, &[])].concat();
+        let file_with_missing_node = VoxFile::parse(&file(&missing_node)).unwrap();
+
+        assert!(matches!(
+            file_with_missing_node.to_world(&mut vec![]),
+            Err(VoxError::MissingNode(5))
+        ));
+
+        let missing_model = [model([1, 1, 1], &[]), transform(0, 1, &[]), shape(1, 3)].concat();
+        let file_with_missing_model = VoxFile::parse(&file(&missing_model)).unwrap();
+
+        assert!(matches!(
+            file_with_missing_model.to_world(&mut vec![]),
+            Err(VoxError::MissingModel(3))
+        ));
+    }
+
+    #[test]
+    fn invalid_transform() {
+        let children = [
+            model([1, 1, 1], &[]),
+            transform(0, 1, &[("_t", "1 2")]),
+            shape(1, 0),
+        ]
+        .concat();
+
+        assert!(matches!(
+            VoxFile::parse(&file(&children)),
+            Err(VoxError::InvalidTranslation(translation)) if "1 2" == translation
+        ));
+    }
+
+    #[test]
+    fn model_with_transform() {
+        let children = [
+            model([2, 2, 2], &[[0, 0, 0, 1], [1, 1, 1, 2]]),
+            transform(0, 1, &[("_t", "10 20 30")]),
+            shape(1, 0),
+        ]
+        .concat();
+
+        let file = VoxFile::parse(&file(&children)).unwrap();
+
+        assert_eq!(1, file.models.len());
+        assert_eq!(UVec3::splat(2), file.models[0].size);
+
+        let world = file.to_world(&mut vec![]).unwrap();
+
+        // models are centered at the translation and `+Z` up becomes `+Y` up
+        let first = IVec3::new(10 - 1, 30 - 1, -1 - (20 - 1));
+        let second = IVec3::new(10, 30, -1 - 20);
+
+        assert_eq!(Voxel::from(file.palette[1]), world.get(first));
+        assert_eq!(Voxel::from(file.palette[2]), world.get(second));
+        assert!(world.get(first + IVec3::X).is_empty());
+    }
+
+    #[test]
+    fn model_without_scene_graph() {
+        let rgba = (0..=255_u8)
+            .flat_map(|i| [i, 0, 0, 255])
+            .collect::<Vec<_>>();
+        let children = [
+            model([1, 1, 1], &[[0, 0, 0, 7]]),
+            chunk(b"RGBA", &rgba, &[]),
+        ]
+        .concat();
+
+        let file = VoxFile::parse(&file(&children)).unwrap();
+        let world = file.to_world(&mut vec![]).unwrap();
+
+        // palette index `i` is stored in the `i - 1`-th color of the `RGBA` chunk
+        assert_eq!(Color::rgb8(6, 0, 0), file.palette[7]);
+        assert_eq!(Voxel::from(Color::rgb8(6, 0, 0)), world.get(IVec3::NEG_Z));
+    }
+
+    #[test]
+    fn materials() {
+        let children = [
+            model([3, 1, 1], &[[0, 0, 0, 1], [1, 0, 0, 2], [2, 0, 0, 3]]),
+            chunk(
+                b"MATL",
+                &[ints(&[1]), dict(&[("_type", "_diffuse")])].concat(),
+                &[],
+            ),
+            chunk(
+                b"MATL",
+                &[ints(&[2]), dict(&[("_type", "_metal"), ("_rough", "0.25")])].concat(),
+                &[],
+            ),
+            chunk(
+                b"MATL",
+                &[
+                    ints(&[3]),
+                    dict(&[("_type", "_glass"), ("_trans", "0.75"), ("_ior", "0.5")]),
+                ]
+                .concat(),
+                &[],
+            ),
+            // unused materials are not added
+            chunk(
+                b"MATL",
+                &[ints(&[4]), dict(&[("_type", "_emit")])].concat(),
+                &[],
+            ),
+        ]
+        .concat();
+
+        let file = VoxFile::parse(&file(&children)).unwrap();
+        let mut materials = vec![Material::default()];
+        let world = file.to_world(&mut materials).unwrap();
+
+        let metal = Material {
+            roughness: 0.25,
+            metalness: 1.0,
+            ..default()
+        };
+        let glass = Material {
+            opacity: 0.25,
+            ior: 1.5,
+            ..default()
+        };
+
+        assert_eq!(vec![Material::default(), metal, glass], materials);
+        assert_eq!(0, world.get(IVec3::new(-1, 0, -1)).material);
+        assert_eq!(1, world.get(IVec3::new(0, 0, -1)).material);
+        assert_eq!(2, world.get(IVec3::new(1, 0, -1)).material);
+    }
+
+    #[test]
+    fn materials_without_scene_materials() {
+        let children = [
+            model([3, 1, 1], &[[0, 0, 0, 1], [1, 0, 0, 2]]),
+            chunk(
+                b"MATL",
+                &[ints(&[2]), dict(&[("_type", "_metal")])].concat(),
+                &[],
+            ),
+        ]
+        .concat();
+
+        let file = VoxFile::parse(&file(&children)).unwrap();
+        let mut materials = vec![];
+        let world = file.to_world(&mut materials).unwrap();
+
+        let metal = Material {
+            metalness: 1.0,
+            ..default()
+        };
+
+        // plain voxels keep the default material in slot 0
+        assert_eq!(vec![Material::default(), metal], materials);
+        assert_eq!(0, world.get(IVec3::new(-1, 0, -1)).material);
+        assert_eq!(1, world.get(IVec3::new(0, 0, -1)).material);
+    }
+
+    #[test]
+    fn invalid_material() {
+        let content = [
+            ints(&[1]),
+            dict(&[("_type", "_metal"), ("_rough", "rough")]),
+        ]
+        .concat();
+
+        assert!(matches!(
+            VoxFile::parse(&file(&chunk(b"MATL", &content, &[]))),
+            Err(VoxError::InvalidMaterialProperty { name, value })
+                if "_rough" == name && "rough" == value
+        ));
+    }
+}
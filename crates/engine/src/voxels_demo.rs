@@ -1,89 +1,309 @@
+use crate::camera::{Camera, CameraUniform};
+use crate::capture::{Image, Recording, SaveImageError};
 use crate::context::*;
-use crate::octree::Chunk;
+use crate::environment::{Environment, EnvironmentUniform};
+use crate::light::{Light, LightBufferHeader, LightUniform};
+use crate::material::{Material, MaterialUniform};
+use crate::octree::FlatNode;
+use crate::pass_constants::PassConstants;
+use crate::profiler::{GpuPass, GpuProfiler};
+use crate::scene::Scene;
+use crate::shading::{AmbientOcclusion, DebugView, RenderMode};
+use crate::tonemapping::Tonemapping;
 use crate::util::default;
+use crate::world::World;
 use bytemuck::{Pod, Zeroable};
 use glam::*;
 use naga::ShaderStage;
-use std::borrow::Cow;
+use std::collections::HashMap;
 use std::num::NonZeroU64;
+use std::path::Path;
 use std::time::Instant;
-use tracing::error;
-use wgpu::util::{BufferInitDescriptor, DeviceExt as _};
+use thiserror::Error;
+use tracing::{debug, error, warn};
+use wgpu::util::{BufferInitDescriptor, DeviceExt as _, TextureDataOrder};
 use wgpu::*;
+use winit::dpi::PhysicalSize;
 
 #[repr(C)]
 #[derive(Clone, Debug, Default, PartialEq, Copy, Pod, Zeroable)]
-struct PushConst {
+pub struct PushConst {
     pub viewport_size: UVec2,
     pub render_texture_size: UVec2,
+    pub chunk_lo: IVec3,
     pub time: f32,
+    pub chunk_count: UVec3,
+    pub render_mode: u32,
+    pub sample_index: u32,
+    pub max_bounces: u32,
+    pub flags: u32,
+    pub ambient_occlusion: u32,
+    pub debug_view: u32,
+}
+
+impl PushConst {
+    pub const SHADOWS: u32 = 1;
+    pub const REFRACTION: u32 = 2;
 }
 
 pub struct VoxelsDemo {
     pub context: RenderContext,
     pub pipeline: ComputePipeline,
     pub binds_layout: BindGroupLayout,
+    pub binds: BindGroup,
+    pub constants: PassConstants<PushConst>,
     pub render_texture: Texture,
+    pub render_texture_view: TextureView,
+    pub accumulation_texture: Texture,
+    pub accumulation_texture_view: TextureView,
+    pub screen_quad: ScreenQuad,
     pub buffer: Buffer,
+    pub chunk_table_buffer: Buffer,
+    pub camera_buffer: Buffer,
+    pub lights_buffer: Buffer,
+    pub materials_buffer: Buffer,
+    pub environment_buffer: Buffer,
+    pub environment_texture: Texture,
+    pub environment_texture_view: TextureView,
+    pub environment_sampler: Sampler,
+    pub camera: Camera,
+    pub lights: Vec<Light>,
+    pub viewport_size: UVec2,
+    /// Render texture size relative to the viewport size
+    pub render_scale: f32,
+    pub render_texture_size: UVec2,
+    pub render_mode: RenderMode,
+    pub max_bounces: u32,
+    /// Trace shadow rays towards the lights
+    pub shadows: bool,
+    /// Bend rays passing through transparent voxels by the index of refraction
+    pub refraction: bool,
+    /// Occlusion of the ambient light in raytrace mode
+    pub ambient_occlusion: AmbientOcclusion,
+    pub debug_view: DebugView,
+    /// Number of samples in the accumulation texture
+    pub sample_index: u32,
+    /// Camera the accumulated samples were taken with
+    pub accumulated_camera: Camera,
+    pub world: World,
+    pub chunk_lo: IVec3,
+    pub chunk_count: UVec3,
     pub creation_instant: Instant,
+    /// Times the raytrace and screen quad passes while enabled
+    pub profiler: Option<GpuProfiler>,
+    /// Every drawn frame is saved while recording
+    pub recording: Option<Recording>,
 }
 
 impl VoxelsDemo {
-    pub const RENDER_TEXTURE_SIZE: UVec2 = UVec2::new(4 * 1024, 4 * 512);
+    pub const MIN_RENDER_SCALE: f32 = 0.25;
+    pub const MAX_RENDER_SCALE: f32 = 2.0;
+    pub const WORKGROUP_SIZE: u32 = 16;
+    pub const ACCUMULATION_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+    /// Linear HDR radiance, tonemapped only when drawn to the screen
+    pub const RENDER_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+    /// Format of frames read back with [`VoxelsDemo::read_frame`]
+    pub const FRAME_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+    /// Binding of [`PushConst`] for devices without push constants
+    pub const CONSTANTS_BINDING: u32 = 10;
 
-    pub fn new(context: RenderContext) -> Self {
-        let chunk = Chunk::new_sphere();
+    pub fn new(context: RenderContext, scene: &Scene) -> Self {
+        let world = scene.world.clone();
+        let flat_world = world.flatten();
 
         let voxel_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
             label: Some("voxel-data"),
-            contents: bytemuck::cast_slice(&chunk.colors),
+            contents: bytemuck::cast_slice(&flat_world.nodes),
             usage: BufferUsages::STORAGE,
         });
 
-        let render_texture = context.device.create_texture(&TextureDescriptor {
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            label: Some("voxels-demo"),
-            mip_level_count: 1,
-            sample_count: 1,
-            size: Extent3d {
-                width: Self::RENDER_TEXTURE_SIZE.x,
-                height: Self::RENDER_TEXTURE_SIZE.y,
-                depth_or_array_layers: 1,
-            },
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[TextureFormat::Rgba8Unorm],
+        let chunk_table_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("chunk-table"),
+            contents: bytemuck::cast_slice(&flat_world.chunk_roots),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let camera = scene.camera;
+
+        let camera_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("camera"),
+            contents: bytemuck::bytes_of(&camera.to_uniform()),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
+        let lights_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("lights"),
+            contents: &Light::buffer_contents(&scene.lights),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let materials_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("materials"),
+            contents: bytemuck::cast_slice(&Material::buffer_contents(&scene.materials)),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let environment_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("environment"),
+            contents: bytemuck::bytes_of(&scene.environment.to_uniform()),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let environment_texture = Self::create_environment_texture(&context, &scene.environment);
+        let environment_texture_view = environment_texture.create_view(&default());
+
+        // the map wraps around horizontally
+        let environment_sampler = context.device.create_sampler(&SamplerDescriptor {
+            label: Some("environment"),
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        let viewport_size = scene.window_size;
+        let render_scale = scene
+            .render_scale
+            .clamp(Self::MIN_RENDER_SCALE, Self::MAX_RENDER_SCALE);
+        let render_texture_size = Self::scaled_size(viewport_size, render_scale);
+        let render_texture = Self::create_render_texture(&context, render_texture_size);
+        let accumulation_texture = Self::create_accumulation_texture(&context, render_texture_size);
+
+        let constants = PassConstants::new(
+            &context,
+            "voxels-demo-constants",
+            ShaderStages::COMPUTE,
+            Self::CONSTANTS_BINDING,
+        );
+
+        let binds_layout_entries = [
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(std::mem::size_of::<FlatNode>() as u64).unwrap(),
+                    ),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: Self::RENDER_FORMAT,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(std::mem::size_of::<u32>() as u64).unwrap(),
+                    ),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(std::mem::size_of::<CameraUniform>() as u64).unwrap(),
+                    ),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(
+                            (std::mem::size_of::<LightBufferHeader>()
+                                + std::mem::size_of::<LightUniform>())
+                                as u64,
+                        )
+                        .unwrap(),
+                    ),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: Self::ACCUMULATION_FORMAT,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 7,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(std::mem::size_of::<EnvironmentUniform>() as u64).unwrap(),
+                    ),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 8,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 9,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 6,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(
+                            (Material::MAX_COUNT * std::mem::size_of::<MaterialUniform>()) as u64,
+                        )
+                        .unwrap(),
+                    ),
+                },
+                count: None,
+            },
+        ];
+
         let binds_layout = context
             .device
             .create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("voxels-demo"),
-                entries: &[
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(
-                                NonZeroU64::new(std::mem::size_of_val(&chunk.colors) as u64)
-                                    .unwrap(),
-                            ),
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
-                            view_dimension: TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                ],
+                entries: &binds_layout_entries
+                    .into_iter()
+                    .chain(constants.layout_entry())
+                    .collect::<Vec<_>>(),
             });
 
         let pipeline_layout = context
@@ -91,20 +311,15 @@ impl VoxelsDemo {
             .create_pipeline_layout(&PipelineLayoutDescriptor {
                 label: Some("vexels-demo"),
                 bind_group_layouts: &[&binds_layout],
-                push_constant_ranges: &[PushConstantRange {
-                    stages: ShaderStages::COMPUTE,
-                    range: 0..std::mem::size_of::<PushConst>() as u32,
-                }],
+                push_constant_ranges: &constants.push_constant_ranges(),
             });
 
-        let voxels_demo_compute = context.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("voxels-demo"),
-            source: ShaderSource::Glsl {
-                shader: Cow::Borrowed(include_str!("../assets/shaders/voxels-demo-compute.glsl")),
-                stage: ShaderStage::Compute,
-                defines: default(),
-            },
-        });
+        let voxels_demo_compute = constants.create_shader_module(
+            &context,
+            "voxels-demo",
+            include_str!("../assets/shaders/voxels-demo-compute.glsl"),
+            ShaderStage::Compute,
+        );
 
         let pipeline = context
             .device
@@ -117,20 +332,534 @@ impl VoxelsDemo {
                 cache: None,
             });
 
+        let render_texture_view = render_texture.create_view(&default());
+        let accumulation_texture_view = accumulation_texture.create_view(&default());
+
+        let binds = Self::create_binds(
+            &context,
+            &binds_layout,
+            [
+                &voxel_buffer,
+                &chunk_table_buffer,
+                &camera_buffer,
+                &lights_buffer,
+                &materials_buffer,
+                &environment_buffer,
+            ],
+            [
+                &render_texture_view,
+                &accumulation_texture_view,
+                &environment_texture_view,
+            ],
+            &environment_sampler,
+            &constants,
+        );
+
+        let mut screen_quad = ScreenQuad::new(&context, &render_texture_view);
+        screen_quad.tonemapping = scene.tonemapping;
+        screen_quad.exposure = scene.exposure;
+        screen_quad.tonemap = DebugView::Off == scene.debug_view;
+
         Self {
             context,
             pipeline,
             binds_layout,
+            binds,
+            constants,
             render_texture,
+            render_texture_view,
+            accumulation_texture,
+            accumulation_texture_view,
+            screen_quad,
             buffer: voxel_buffer,
+            chunk_table_buffer,
+            camera_buffer,
+            lights_buffer,
+            materials_buffer,
+            environment_buffer,
+            environment_texture,
+            environment_texture_view,
+            environment_sampler,
+            camera,
+            lights: scene.lights.clone(),
+            viewport_size,
+            render_scale,
+            render_texture_size,
+            render_mode: scene.render_mode,
+            max_bounces: scene.max_bounces,
+            shadows: scene.shadows,
+            refraction: scene.refraction,
+            ambient_occlusion: scene.ambient_occlusion,
+            debug_view: scene.debug_view,
+            sample_index: 0,
+            accumulated_camera: camera,
+            world,
+            chunk_lo: flat_world.chunk_lo,
+            chunk_count: flat_world.chunk_count,
             creation_instant: Instant::now(),
+            profiler: None,
+            recording: None,
+        }
+    }
+
+    pub fn scaled_size(viewport_size: UVec2, render_scale: f32) -> UVec2 {
+        (render_scale * viewport_size.as_vec2())
+            .round()
+            .as_uvec2()
+            .max(UVec2::ONE)
+    }
+
+    /// Environment map texture or a single black texel if the environment has no map
+    /// Maps larger than the device supports are downsampled to fit
+    fn create_environment_texture(context: &RenderContext, environment: &Environment) -> Texture {
+        let max_size = context.device.limits().max_texture_dimension_2d;
+
+        let downsampled = match environment {
+            Environment::Map(map) => map.downsampled(max_size).inspect(|downsampled| {
+                warn!(
+                    size = %map.size,
+                    max_size,
+                    new_size = %downsampled.size,
+                    "environment map is too large for the device, downsampled"
+                );
+            }),
+            _ => None,
+        };
+
+        let (size, texels) = match (downsampled.as_ref(), environment) {
+            (Some(map), _) | (None, Environment::Map(map)) => (map.size, map.texels.as_slice()),
+            _ => (UVec2::ONE, [0].as_slice()),
+        };
+
+        context.device.create_texture_with_data(
+            &context.queue,
+            &TextureDescriptor {
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgb9e5Ufloat,
+                label: Some("environment"),
+                mip_level_count: 1,
+                sample_count: 1,
+                size: Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+                usage: TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(texels),
+        )
+    }
+
+    fn create_render_texture(context: &RenderContext, size: UVec2) -> Texture {
+        context.device.create_texture(&TextureDescriptor {
+            dimension: TextureDimension::D2,
+            format: Self::RENDER_FORMAT,
+            label: Some("voxels-demo"),
+            mip_level_count: 1,
+            sample_count: 1,
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[Self::RENDER_FORMAT],
+        })
+    }
+
+    fn create_accumulation_texture(context: &RenderContext, size: UVec2) -> Texture {
+        context.device.create_texture(&TextureDescriptor {
+            dimension: TextureDimension::D2,
+            format: Self::ACCUMULATION_FORMAT,
+            label: Some("voxels-demo-accumulation"),
+            mip_level_count: 1,
+            sample_count: 1,
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            usage: TextureUsages::STORAGE_BINDING,
+            view_formats: &[Self::ACCUMULATION_FORMAT],
+        })
+    }
+
+    /// `buffers` are nodes, chunk table, camera, lights, materials and environment buffers,
+    /// `texture_views` are render, accumulation and environment texture views
+    fn create_binds(
+        context: &RenderContext,
+        layout: &BindGroupLayout,
+        buffers: [&Buffer; 6],
+        texture_views: [&TextureView; 3],
+        environment_sampler: &Sampler,
+        constants: &PassConstants<PushConst>,
+    ) -> BindGroup {
+        let [nodes, chunk_table, camera, lights, materials, environment] = buffers;
+        let [render_texture_view, accumulation_texture_view, environment_texture_view] =
+            texture_views;
+
+        let entries = [
+            BindGroupEntry {
+                binding: 0,
+                resource: nodes.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(render_texture_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: chunk_table.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: camera.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: lights.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: BindingResource::TextureView(accumulation_texture_view),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: materials.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: environment.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: BindingResource::TextureView(environment_texture_view),
+            },
+            BindGroupEntry {
+                binding: 9,
+                resource: BindingResource::Sampler(environment_sampler),
+            },
+        ];
+
+        context.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("voxels-demo"),
+            layout,
+            entries: &entries
+                .into_iter()
+                .chain(constants.bind_group_entry())
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Re-creates the render texture at `viewport_size * render_scale` if its size changes
+    pub fn resize(&mut self, viewport_size: UVec2) {
+        self.viewport_size = viewport_size;
+
+        let render_texture_size = Self::scaled_size(viewport_size, self.render_scale);
+
+        if render_texture_size == self.render_texture_size {
+            return;
         }
+
+        self.render_texture_size = render_texture_size;
+        self.render_texture = Self::create_render_texture(&self.context, render_texture_size);
+        self.render_texture_view = self.render_texture.create_view(&default());
+        self.accumulation_texture =
+            Self::create_accumulation_texture(&self.context, render_texture_size);
+        self.accumulation_texture_view = self.accumulation_texture.create_view(&default());
+        self.reset_accumulation();
+
+        self.binds = Self::create_binds(
+            &self.context,
+            &self.binds_layout,
+            [
+                &self.buffer,
+                &self.chunk_table_buffer,
+                &self.camera_buffer,
+                &self.lights_buffer,
+                &self.materials_buffer,
+                &self.environment_buffer,
+            ],
+            [
+                &self.render_texture_view,
+                &self.accumulation_texture_view,
+                &self.environment_texture_view,
+            ],
+            &self.environment_sampler,
+            &self.constants,
+        );
+
+        self.screen_quad
+            .set_texture(&self.context, &self.render_texture_view);
+    }
+
+    /// Clamps `render_scale` to `MIN_RENDER_SCALE..=MAX_RENDER_SCALE` and resizes the render texture
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale = render_scale.clamp(Self::MIN_RENDER_SCALE, Self::MAX_RENDER_SCALE);
+        self.resize(self.viewport_size);
+    }
+
+    /// Restarts path tracing accumulation, call it after changing the scene
+    pub fn reset_accumulation(&mut self) {
+        self.sample_index = 0;
+    }
+
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.render_mode = render_mode;
+        self.reset_accumulation();
+    }
+
+    pub fn set_refraction(&mut self, refraction: bool) {
+        self.refraction = refraction;
+        self.reset_accumulation();
+    }
+
+    pub fn set_shadows(&mut self, shadows: bool) {
+        self.shadows = shadows;
+        self.reset_accumulation();
+    }
+
+    pub fn set_ambient_occlusion(&mut self, ambient_occlusion: AmbientOcclusion) {
+        self.ambient_occlusion = ambient_occlusion;
+        self.reset_accumulation();
+    }
+
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+        self.screen_quad.tonemap = DebugView::Off == debug_view;
+        self.reset_accumulation();
     }
 
-    pub fn draw(&self) {
-        let Ok(cur_texture) = self.context.surface.get_current_texture() else {
-            error!("no next swapchain texture");
+    /// Enabling fails with a warning if the device has no timestamp queries
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if !enabled {
+            self.profiler = None;
             return;
+        }
+
+        if self.profiler.is_none() {
+            self.profiler = GpuProfiler::new(&self.context);
+        }
+
+        if self.profiler.is_none() {
+            warn!("timestamp queries are not supported, GPU profiling is unavailable");
+        }
+    }
+
+    pub fn encode_raytrace(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        viewport_size: UVec2,
+        time: f32,
+    ) {
+        if self.camera != self.accumulated_camera {
+            self.accumulated_camera = self.camera;
+            self.reset_accumulation();
+        }
+
+        let sample_index = self.sample_index;
+
+        if RenderMode::PathTrace == self.render_mode {
+            self.sample_index = self.sample_index.saturating_add(1);
+        }
+
+        self.context.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::bytes_of(&self.camera.to_uniform()),
+        );
+
+        let timestamp_writes = self
+            .profiler
+            .as_mut()
+            .and_then(|profiler| profiler.compute_timestamp_writes(GpuPass::Raytrace));
+
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("voxels-demo"),
+            timestamp_writes,
+        });
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.binds, &[]);
+        self.constants.set_compute(
+            &self.context,
+            &mut pass,
+            &PushConst {
+                viewport_size,
+                render_texture_size: self.render_texture_size,
+                chunk_lo: self.chunk_lo,
+                time,
+                chunk_count: self.chunk_count,
+                render_mode: self.render_mode as u32,
+                sample_index,
+                max_bounces: self.max_bounces,
+                flags: (PushConst::SHADOWS * self.shadows as u32)
+                    | (PushConst::REFRACTION * self.refraction as u32),
+                ambient_occlusion: self.ambient_occlusion as u32,
+                debug_view: self.debug_view as u32,
+            },
+        );
+        pass.dispatch_workgroups(
+            self.render_texture_size.x.div_ceil(Self::WORKGROUP_SIZE),
+            self.render_texture_size.y.div_ceil(Self::WORKGROUP_SIZE),
+            1,
+        );
+    }
+
+    /// Raytraces the scene into `render_texture` without presenting it anywhere
+    pub fn render_offscreen(&mut self, time: f32) {
+        self.begin_profiling();
+
+        let mut encoder = self.context.device.create_command_encoder(&default());
+
+        self.encode_raytrace(&mut encoder, self.viewport_size, time);
+        self.submit(encoder);
+    }
+
+    fn begin_profiling(&mut self) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.poll(&self.context.device);
+            profiler.begin_frame();
+        }
+    }
+
+    /// Submits the frame resolving the profiler timestamps
+    fn submit(&mut self, mut encoder: CommandEncoder) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.resolve(&mut encoder);
+        }
+
+        self.context.queue.submit([encoder.finish()]);
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
+    }
+
+    /// Tonemaps the render texture into an sRGB image of the same size, the blit is not profiled
+    /// as it is not part of the frame
+    pub fn read_frame(&mut self) -> Result<Image, BufferAsyncError> {
+        let frame = self.context.device.create_texture(&TextureDescriptor {
+            dimension: TextureDimension::D2,
+            format: Self::FRAME_FORMAT,
+            label: Some("voxels-demo-frame"),
+            mip_level_count: 1,
+            sample_count: 1,
+            size: self.render_texture.size(),
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[Self::FRAME_FORMAT],
+        });
+
+        let mut encoder = self.context.device.create_command_encoder(&default());
+
+        self.screen_quad.encode_draw(
+            &self.context,
+            &mut encoder,
+            &frame.create_view(&default()),
+            Self::FRAME_FORMAT,
+            self.render_texture_size,
+            None,
+        );
+
+        self.context.queue.submit([encoder.finish()]);
+
+        Image::from_texture(&self.context, &frame)
+    }
+
+    /// Saves the latest frame tonemapped at the render texture size
+    pub fn save_screenshot(&mut self, path: impl AsRef<Path>) -> Result<(), SaveImageError> {
+        self.read_frame()?.save(path)
+    }
+
+    /// Time passed to the shader, fixed per frame while recording
+    pub fn time(&self) -> f32 {
+        match self.recording.as_ref() {
+            Some(recording) => recording.time(),
+            None => self.creation_instant.elapsed().as_secs_f32(),
+        }
+    }
+
+    /// Rebuilds all GPU resources on `context` from the CPU-side data keeping the state changed
+    /// at runtime, `scene` provides the rest, e.g. after the device has been lost
+    pub fn recreate(&mut self, context: RenderContext, scene: &Scene) {
+        let scene = Scene {
+            window_size: self.viewport_size,
+            render_scale: self.render_scale,
+            render_mode: self.render_mode,
+            max_bounces: self.max_bounces,
+            shadows: self.shadows,
+            refraction: self.refraction,
+            ambient_occlusion: self.ambient_occlusion,
+            tonemapping: self.screen_quad.tonemapping,
+            exposure: self.screen_quad.exposure,
+            debug_view: self.debug_view,
+            gpu_profiling: self.profiler.is_some(),
+            camera: self.camera,
+            lights: self.lights.clone(),
+            world: self.world.clone(),
+            ..scene.clone()
+        };
+
+        let mut voxels_demo = Self::new(context, &scene);
+        voxels_demo.set_profiling(scene.gpu_profiling);
+        voxels_demo.creation_instant = self.creation_instant;
+        voxels_demo.recording = self.recording.take();
+
+        *self = voxels_demo;
+    }
+
+    /// Acquires the next swapchain texture reconfiguring the surface once if it is outdated
+    /// or lost, `None` if the frame should be skipped
+    fn next_surface_texture(
+        &self,
+        surface: &Surface<'_>,
+        window_size: UVec2,
+    ) -> Result<Option<SurfaceTexture>, DrawError> {
+        for _ in 0..2 {
+            match surface.get_current_texture() {
+                Ok(texture) => return Ok(Some(texture)),
+                Err(SurfaceError::Timeout) => {
+                    warn!("timed out waiting for the next swapchain texture, frame skipped");
+                    return Ok(None);
+                }
+                Err(SurfaceError::OutOfMemory) => return Err(DrawError::OutOfMemory),
+                Err(err @ (SurfaceError::Outdated | SurfaceError::Lost)) => {
+                    debug!(%err, "reconfiguring the surface");
+
+                    let size = PhysicalSize::new(window_size.x, window_size.y);
+
+                    if let Err(err) = self.context.resize(size) {
+                        error!(%err, "failed to reconfigure the surface, frame skipped");
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+
+        warn!("surface is still outdated after reconfiguring, frame skipped");
+        Ok(None)
+    }
+
+    /// Draws a frame onto the surface of `window_size`, frames are skipped while it is zero
+    pub fn draw(&mut self, window_size: UVec2) -> Result<(), DrawError> {
+        if self.context.poll_device_lost() {
+            return Err(DrawError::DeviceLost);
+        }
+
+        let Some(surface) = self.context.surface.clone() else {
+            error!("no surface to draw to");
+            return Ok(());
+        };
+
+        // minimized windows have nothing to draw to
+        if window_size.cmpeq(UVec2::ZERO).any() {
+            return Ok(());
+        }
+
+        let Some(cur_texture) = self.next_surface_texture(&surface, window_size)? else {
+            return Ok(());
         };
 
         let viewport_size = {
@@ -138,224 +867,315 @@ impl VoxelsDemo {
             UVec2::new(extent.width, extent.height)
         };
 
+        if viewport_size != self.viewport_size {
+            self.resize(viewport_size);
+        }
+
+        self.begin_profiling();
+
         let screen_view = cur_texture.texture.create_view(&default());
         let mut encoder = self.context.device.create_command_encoder(&default());
 
-        {
-            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-                label: Some("voxels-demo"),
-                timestamp_writes: None,
-            });
+        self.encode_raytrace(&mut encoder, viewport_size, self.time());
 
-            let render_texture_view = self.render_texture.create_view(&default());
+        self.screen_quad.encode_draw(
+            &self.context,
+            &mut encoder,
+            &screen_view,
+            cur_texture.texture.format(),
+            viewport_size,
+            self.profiler
+                .as_mut()
+                .and_then(|profiler| profiler.render_timestamp_writes(GpuPass::ScreenQuad)),
+        );
 
-            let bind = self.context.device.create_bind_group(&BindGroupDescriptor {
-                label: Some("voxels-demo"),
-                layout: &self.binds_layout,
-                entries: &[
-                    BindGroupEntry {
-                        binding: 0,
-                        resource: self.buffer.as_entire_binding(),
-                    },
-                    BindGroupEntry {
-                        binding: 1,
-                        resource: BindingResource::TextureView(&render_texture_view),
-                    },
-                ],
-            });
+        self.submit(encoder);
 
-            pass.set_pipeline(&self.pipeline);
-            pass.set_bind_group(0, &bind, &[]);
-            pass.set_push_constants(
-                0,
-                bytemuck::bytes_of(&PushConst {
-                    viewport_size,
-                    render_texture_size: Self::RENDER_TEXTURE_SIZE,
-                    time: self.creation_instant.elapsed().as_secs_f32(),
-                }),
-            );
-            pass.dispatch_workgroups(
-                Self::RENDER_TEXTURE_SIZE.x / 16,
-                Self::RENDER_TEXTURE_SIZE.y / 16,
-                1,
-            );
+        if let Some(path) = self.recording.as_ref().map(Recording::frame_path) {
+            if let Err(err) = self.save_screenshot(&path) {
+                error!(?path, %err, "failed to save a recorded frame, recording stopped");
+                self.recording = None;
+            }
         }
 
-        {
-            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("voxels-demo"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &screen_view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(wgpu::Color::BLACK),
-                        store: StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
+        if let Some(recording) = self.recording.as_mut() {
+            recording.n_frames += 1;
+        }
+
+        cur_texture.present();
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DrawError {
+    #[error("device is lost, the render context has to be re-created")]
+    DeviceLost,
+    #[error("out of memory acquiring the next swapchain texture")]
+    OutOfMemory,
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, Copy, Pod, Zeroable)]
+pub struct ScreenQuadPushConst {
+    pub viewport_size: UVec2,
+    pub exposure: f32,
+    pub tonemapping: u32,
+    pub flags: u32,
+}
+
+impl ScreenQuadPushConst {
+    pub const ENCODE_SRGB: u32 = 1;
+    pub const TONEMAP: u32 = 2;
+}
+
+/// Stretches an HDR texture over the whole render target tonemapping it
+pub struct ScreenQuad {
+    pub vertex_buffer: Buffer,
+    pub sampler: Sampler,
+    pub binds_layout: BindGroupLayout,
+    pub binds: BindGroup,
+    pub constants: PassConstants<ScreenQuadPushConst>,
+    pub pipeline_layout: PipelineLayout,
+    pub vertex_shader: ShaderModule,
+    pub fragment_shader: ShaderModule,
+    /// Created on the first draw to a target of each format, e.g. the surface and captures
+    pub pipelines: HashMap<TextureFormat, RenderPipeline>,
+    pub tonemapping: Tonemapping,
+    /// Scales the radiance by `2^exposure` before tonemapping
+    pub exposure: f32,
+    /// Off for textures holding display colors already
+    pub tonemap: bool,
+}
+
+impl ScreenQuad {
+    pub const VERTICES: [Vec2; 6] = [
+        Vec2::new(-1.0, -1.0),
+        Vec2::new(1.0, -1.0),
+        Vec2::new(1.0, 1.0),
+        Vec2::new(-1.0, -1.0),
+        Vec2::new(1.0, 1.0),
+        Vec2::new(-1.0, 1.0),
+    ];
+    /// Binding of [`ScreenQuadPushConst`] for devices without push constants
+    pub const CONSTANTS_BINDING: u32 = 2;
+
+    pub fn new(context: &RenderContext, texture_view: &TextureView) -> Self {
+        let vertex_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
+            contents: bytemuck::cast_slice(&Self::VERTICES),
+            label: Some("screen-quad"),
+            usage: BufferUsages::VERTEX,
+        });
+
+        let sampler = context.device.create_sampler(&default());
+
+        let constants = PassConstants::new(
+            context,
+            "screen-quad-constants",
+            ShaderStages::VERTEX_FRAGMENT,
+            Self::CONSTANTS_BINDING,
+        );
+
+        let binds_layout_entries = [
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+
+        let binds_layout = context
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("screen-quad"),
+                entries: &binds_layout_entries
+                    .into_iter()
+                    .chain(constants.layout_entry())
+                    .collect::<Vec<_>>(),
             });
 
-            let vertices = [
-                Vec2::new(-1.0, -1.0),
-                Vec2::new(1.0, -1.0),
-                Vec2::new(1.0, 1.0),
-                Vec2::new(-1.0, -1.0),
-                Vec2::new(1.0, 1.0),
-                Vec2::new(-1.0, 1.0),
-            ];
-
-            let vertex_buffer =
-                self.context
-                    .device
-                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        contents: bytemuck::cast_slice(&vertices),
-                        label: None,
-                        usage: wgpu::BufferUsages::VERTEX,
-                    });
-
-            let view = self.render_texture.create_view(&default());
-            let sampler = self.context.device.create_sampler(&default());
-
-            let binds_layout =
-                self.context
-                    .device
-                    .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                        label: Some("voxels-demo"),
-                        entries: &[
-                            BindGroupLayoutEntry {
-                                binding: 0,
-                                visibility: ShaderStages::FRAGMENT,
-                                ty: BindingType::Texture {
-                                    sample_type: TextureSampleType::Float { filterable: true },
-                                    view_dimension: TextureViewDimension::D2,
-                                    multisampled: false,
-                                },
-                                count: None,
-                            },
-                            BindGroupLayoutEntry {
-                                binding: 1,
-                                visibility: ShaderStages::FRAGMENT,
-                                ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                                count: None,
-                            },
-                        ],
-                    });
-
-            let binds = self.context.device.create_bind_group(&BindGroupDescriptor {
-                label: Some("voxels-demo"),
-                layout: &binds_layout,
-                entries: &[
-                    BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::TextureView(&view),
-                    },
-                    BindGroupEntry {
-                        binding: 1,
-                        resource: BindingResource::Sampler(&sampler),
-                    },
-                ],
+        let binds = Self::create_binds(context, &binds_layout, texture_view, &sampler, &constants);
+
+        let pipeline_layout = context
+            .device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("screen-quad"),
+                bind_group_layouts: &[&binds_layout],
+                push_constant_ranges: &constants.push_constant_ranges(),
             });
 
-            let pipeline_layout =
-                self.context
-                    .device
-                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: None,
-                        bind_group_layouts: &[&binds_layout],
-                        push_constant_ranges: &[wgpu::PushConstantRange {
-                            stages: wgpu::ShaderStages::VERTEX,
-                            range: 0..std::mem::size_of::<UVec2>() as u32,
-                        }],
-                    });
-
-            let vertex_shader =
-                self.context
-                    .device
-                    .create_shader_module(wgpu::ShaderModuleDescriptor {
-                        label: None,
-                        source: wgpu::ShaderSource::Glsl {
-                            shader: Cow::Borrowed(include_str!(
-                                "../assets/shaders/screen-quad-vertex.glsl"
-                            )),
-                            stage: wgpu::naga::ShaderStage::Vertex,
-                            defines: default(),
-                        },
-                    });
-
-            let fragment_shader =
-                self.context
-                    .device
-                    .create_shader_module(wgpu::ShaderModuleDescriptor {
-                        label: None,
-                        source: wgpu::ShaderSource::Glsl {
-                            shader: Cow::Borrowed(include_str!(
-                                "../assets/shaders/screen-quad-fragment.glsl"
-                            )),
-                            stage: wgpu::naga::ShaderStage::Fragment,
-                            defines: default(),
-                        },
-                    });
-
-            let pipeline =
-                self.context
-                    .device
-                    .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                        label: None,
-                        layout: Some(&pipeline_layout),
-                        vertex: wgpu::VertexState {
-                            module: &vertex_shader,
-                            entry_point: Some("main"),
-                            compilation_options: default(),
-                            buffers: &[wgpu::VertexBufferLayout {
-                                array_stride: std::mem::size_of::<Vec2>() as u64,
-                                step_mode: wgpu::VertexStepMode::Vertex,
-                                attributes: &wgpu::vertex_attr_array![0 => Float32x2],
-                            }],
-                        },
-                        fragment: Some(wgpu::FragmentState {
-                            module: &fragment_shader,
-                            entry_point: Some("main"),
-                            compilation_options: default(),
-                            targets: &[Some(wgpu::ColorTargetState {
-                                format: cur_texture.texture.format(),
-                                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                                write_mask: wgpu::ColorWrites::ALL,
-                            })],
-                        }),
-                        cache: None,
-                        primitive: wgpu::PrimitiveState {
-                            topology: wgpu::PrimitiveTopology::TriangleList,
-                            strip_index_format: None,
-                            front_face: wgpu::FrontFace::Ccw,
-                            cull_mode: None,
-                            unclipped_depth: false,
-                            polygon_mode: wgpu::PolygonMode::Fill,
-                            conservative: false,
-                        },
-                        depth_stencil: None,
-                        multisample: wgpu::MultisampleState {
-                            count: 1,
-                            mask: !0,
-                            alpha_to_coverage_enabled: false,
-                        },
-                        multiview: None,
-                    });
-
-            pass.set_pipeline(&pipeline);
-            pass.set_bind_group(0, &binds, &[]);
-            pass.set_push_constants(
-                wgpu::ShaderStages::VERTEX,
-                0,
-                bytemuck::bytes_of(&viewport_size),
-            );
-            pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-            pass.draw(0..vertices.len() as u32, 0..1);
+        let vertex_shader = constants.create_shader_module(
+            context,
+            "screen-quad-vertex",
+            include_str!("../assets/shaders/screen-quad-vertex.glsl"),
+            ShaderStage::Vertex,
+        );
+
+        let fragment_shader = constants.create_shader_module(
+            context,
+            "screen-quad-fragment",
+            include_str!("../assets/shaders/screen-quad-fragment.glsl"),
+            ShaderStage::Fragment,
+        );
+
+        Self {
+            vertex_buffer,
+            sampler,
+            binds_layout,
+            binds,
+            constants,
+            pipeline_layout,
+            vertex_shader,
+            fragment_shader,
+            pipelines: HashMap::new(),
+            tonemapping: default(),
+            exposure: 0.0,
+            tonemap: true,
         }
+    }
 
-        self.context.queue.submit([encoder.finish()]);
+    fn create_binds(
+        context: &RenderContext,
+        layout: &BindGroupLayout,
+        texture_view: &TextureView,
+        sampler: &Sampler,
+        constants: &PassConstants<ScreenQuadPushConst>,
+    ) -> BindGroup {
+        let entries = [
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ];
 
-        cur_texture.present();
+        context.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("screen-quad"),
+            layout,
+            entries: &entries
+                .into_iter()
+                .chain(constants.bind_group_entry())
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Rebinds the quad to another texture, e.g. after the render texture has been re-created
+    pub fn set_texture(&mut self, context: &RenderContext, texture_view: &TextureView) {
+        self.binds = Self::create_binds(
+            context,
+            &self.binds_layout,
+            texture_view,
+            &self.sampler,
+            &self.constants,
+        );
+    }
+
+    fn create_pipeline(&self, context: &RenderContext, format: TextureFormat) -> RenderPipeline {
+        context
+            .device
+            .create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("screen-quad"),
+                layout: Some(&self.pipeline_layout),
+                vertex: VertexState {
+                    module: &self.vertex_shader,
+                    entry_point: Some("main"),
+                    compilation_options: default(),
+                    buffers: &[VertexBufferLayout {
+                        array_stride: std::mem::size_of::<Vec2>() as u64,
+                        step_mode: VertexStepMode::Vertex,
+                        attributes: &vertex_attr_array![0 => Float32x2],
+                    }],
+                },
+                fragment: Some(FragmentState {
+                    module: &self.fragment_shader,
+                    entry_point: Some("main"),
+                    compilation_options: default(),
+                    targets: &[Some(ColorTargetState {
+                        format,
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                cache: None,
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+    }
+
+    pub fn encode_draw(
+        &mut self,
+        context: &RenderContext,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        target_format: TextureFormat,
+        viewport_size: UVec2,
+        timestamp_writes: Option<RenderPassTimestampWrites<'_>>,
+    ) {
+        if !self.pipelines.contains_key(&target_format) {
+            let pipeline = self.create_pipeline(context, target_format);
+            self.pipelines.insert(target_format, pipeline);
+        }
+
+        let pipeline = &self.pipelines[&target_format];
+
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("screen-quad"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes,
+            occlusion_query_set: None,
+        });
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.binds, &[]);
+        self.constants.set_render(
+            context,
+            &mut pass,
+            &ScreenQuadPushConst {
+                viewport_size,
+                exposure: self.exposure,
+                tonemapping: self.tonemapping as u32,
+                flags: (ScreenQuadPushConst::ENCODE_SRGB * !target_format.is_srgb() as u32)
+                    | (ScreenQuadPushConst::TONEMAP * self.tonemap as u32),
+            },
+        );
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..Self::VERTICES.len() as u32, 0..1);
     }
 }
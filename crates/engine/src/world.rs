@@ -0,0 +1,370 @@
+use crate::octree::{Chunk, Color, FlatNode, Node, Octree, Voxel};
+use glam::*;
+use std::collections::HashMap;
+
+/// Voxel world made of [`Chunk`]s indexed by chunk coordinates,
+/// chunk at `pos` spans voxels from `Chunk::SIZE * pos` to `Chunk::SIZE * (pos + 1)`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct World {
+    pub chunks: HashMap<IVec3, Chunk>,
+}
+
+impl World {
+    pub const EMPTY_CHUNK: Chunk = Chunk::all_same(Voxel::EMPTY);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_sphere(radius: f32) -> Self {
+        let mut world = Self::new();
+        let extent = radius.ceil() as i32;
+
+        for z in -extent..extent {
+            for y in -extent..extent {
+                for x in -extent..extent {
+                    let pos = IVec3::new(x, y, z);
+
+                    if (pos.as_vec3() + 0.5).length() >= radius {
+                        continue;
+                    }
+
+                    let color = 0.5 + 0.5 * pos.as_vec3() / radius;
+                    world.set(pos, Color::from_vec3(color).into());
+                }
+            }
+        }
+
+        world
+    }
+
+    pub fn chunk_pos(voxel_pos: IVec3) -> IVec3 {
+        voxel_pos.div_euclid(IVec3::splat(Chunk::SIZE as i32))
+    }
+
+    pub fn local_pos(voxel_pos: IVec3) -> UVec3 {
+        voxel_pos
+            .rem_euclid(IVec3::splat(Chunk::SIZE as i32))
+            .as_uvec3()
+    }
+
+    pub fn chunk(&self, chunk_pos: IVec3) -> Option<&Chunk> {
+        self.chunks.get(&chunk_pos)
+    }
+
+    pub fn chunk_mut(&mut self, chunk_pos: IVec3) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&chunk_pos)
+    }
+
+    pub fn insert_chunk(&mut self, chunk_pos: IVec3, chunk: Chunk) -> Option<Chunk> {
+        self.chunks.insert(chunk_pos, chunk)
+    }
+
+    pub fn remove_chunk(&mut self, chunk_pos: IVec3) -> Option<Chunk> {
+        self.chunks.remove(&chunk_pos)
+    }
+
+    /// Voxel at world position `pos`, voxels of missing chunks are empty
+    pub fn get(&self, pos: IVec3) -> Voxel {
+        self.chunk(Self::chunk_pos(pos))
+            .map(|chunk| chunk.get(Self::local_pos(pos)))
+            .unwrap_or(Voxel::EMPTY)
+    }
+
+    /// Sets the voxel at world position `pos` creating an empty chunk if needed
+    pub fn set(&mut self, pos: IVec3, voxel: Voxel) {
+        let chunk_pos = Self::chunk_pos(pos);
+
+        if voxel.is_empty() && !self.chunks.contains_key(&chunk_pos) {
+            return;
+        }
+
+        self.chunks
+            .entry(chunk_pos)
+            .or_insert(Self::EMPTY_CHUNK)
+            .set(Self::local_pos(pos), voxel);
+    }
+
+    pub fn clear(&mut self, pos: IVec3) {
+        self.set(pos, Voxel::EMPTY);
+    }
+
+    /// Assigns `material` to all non-empty voxels
+    pub fn set_material(&mut self, material: u8) {
+        for chunk in self.chunks.values_mut() {
+            for voxel in &mut chunk.voxels {
+                if !voxel.is_empty() {
+                    voxel.material = material;
+                }
+            }
+        }
+    }
+
+    /// Copies all non-empty voxels of `other` shifted by `offset` into this world
+    pub fn paste(&mut self, other: &Self, offset: IVec3) {
+        let chunk_size = Chunk::SIZE as i32;
+
+        for (&chunk_pos, chunk) in &other.chunks {
+            for (index, &voxel) in chunk.voxels.iter().enumerate() {
+                if voxel.is_empty() {
+                    continue;
+                }
+
+                let local_pos = IVec3::new(
+                    index as i32 % chunk_size,
+                    index as i32 / chunk_size % chunk_size,
+                    index as i32 / (chunk_size * chunk_size),
+                );
+
+                self.set(chunk_size * chunk_pos + local_pos + offset, voxel);
+            }
+        }
+    }
+
+    /// Range of chunk coordinates `lo..hi` covering all chunks
+    pub fn chunk_bounds(&self) -> Option<(IVec3, IVec3)> {
+        self.chunks.keys().fold(None, |bounds, &pos| {
+            Some(match bounds {
+                None => (pos, pos + 1),
+                Some((lo, hi)) => (lo.min(pos), hi.max(pos + 1)),
+            })
+        })
+    }
+
+    pub fn flatten(&self) -> FlatWorld {
+        let Some((lo, hi)) = self.chunk_bounds() else {
+            return FlatWorld {
+                chunk_lo: IVec3::ZERO,
+                chunk_count: UVec3::ONE,
+                chunk_roots: vec![FlatWorld::NO_CHUNK],
+                nodes: vec![FlatNode::leaf(Voxel::EMPTY)],
+            };
+        };
+
+        let chunk_count = (hi - lo).as_uvec3();
+        let mut chunk_roots = vec![FlatWorld::NO_CHUNK; chunk_count.element_product() as usize];
+        let mut nodes = vec![];
+
+        for (&pos, chunk) in &self.chunks {
+            let octree = Octree::from_chunk(chunk);
+
+            if octree.root == Node::EMPTY {
+                continue;
+            }
+
+            let index = FlatWorld::chunk_index(chunk_count, (pos - lo).as_uvec3());
+            chunk_roots[index] = octree.flatten_into(&mut nodes);
+        }
+
+        if nodes.is_empty() {
+            nodes.push(FlatNode::leaf(Voxel::EMPTY));
+        }
+
+        FlatWorld {
+            chunk_lo: lo,
+            chunk_count,
+            chunk_roots,
+            nodes,
+        }
+    }
+}
+
+/// [`World`] layout as the compute shader reads it: a dense chunk lookup table
+/// with octree roots of each chunk and all chunk octrees packed into one node array
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlatWorld {
+    pub chunk_lo: IVec3,
+    pub chunk_count: UVec3,
+    pub chunk_roots: Vec<u32>,
+    pub nodes: Vec<FlatNode>,
+}
+
+impl FlatWorld {
+    pub const NO_CHUNK: u32 = u32::MAX;
+
+    pub const fn chunk_index(chunk_count: UVec3, table_pos: UVec3) -> usize {
+        ((chunk_count.y * table_pos.z + table_pos.y) * chunk_count.x + table_pos.x) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Looks up a voxel in the chunk table and the chunk octree like the shaders do
+    fn flat_get(flat: &FlatWorld, pos: IVec3) -> Voxel {
+        let table_pos = World::chunk_pos(pos) - flat.chunk_lo;
+
+        if table_pos.cmplt(IVec3::ZERO).any() || table_pos.cmpge(flat.chunk_count.as_ivec3()).any()
+        {
+            return Voxel::EMPTY;
+        }
+
+        let root = flat.chunk_roots[FlatWorld::chunk_index(flat.chunk_count, table_pos.as_uvec3())];
+
+        if FlatWorld::NO_CHUNK == root {
+            return Voxel::EMPTY;
+        }
+
+        let local_pos = World::local_pos(pos);
+        let mut node = flat.nodes[root as usize];
+        let mut level = Octree::CHUNK_DEPTH;
+
+        while FlatNode::BRANCH == node.kind {
+            node = flat.nodes[node.data as usize + Node::child_index(local_pos, level)];
+            level -= 1;
+        }
+
+        node.voxel()
+    }
+
+    #[test]
+    fn chunk_and_local_positions() {
+        assert_eq!(IVec3::ZERO, World::chunk_pos(IVec3::new(0, 15, 7)));
+        assert_eq!(
+            IVec3::new(-1, 1, 0),
+            World::chunk_pos(IVec3::new(-1, 16, 0))
+        );
+        assert_eq!(IVec3::splat(-2), World::chunk_pos(IVec3::splat(-17)));
+
+        assert_eq!(
+            UVec3::new(15, 0, 1),
+            World::local_pos(IVec3::new(-1, 16, 33))
+        );
+        assert_eq!(UVec3::splat(15), World::local_pos(IVec3::splat(-17)));
+    }
+
+    #[test]
+    fn set_get_clear() {
+        let mut world = World::new();
+        let pos = IVec3::new(-3, 20, 5);
+        let voxel = Voxel::new(Color::RED, 2);
+
+        world.set(pos, voxel);
+
+        assert_eq!(voxel, world.get(pos));
+        assert!(world.get(pos + IVec3::Y).is_empty());
+        assert!(world.chunk(IVec3::new(-1, 1, 0)).is_some());
+
+        world.clear(pos);
+
+        assert!(world.get(pos).is_empty());
+    }
+
+    #[test]
+    fn clearing_missing_chunk_creates_nothing() {
+        let mut world = World::new();
+        world.clear(IVec3::new(100, 0, 0));
+
+        assert!(world.chunks.is_empty());
+        assert_eq!(None, world.chunk_bounds());
+    }
+
+    #[test]
+    fn paste_with_offset() {
+        let mut model = World::new();
+        model.set(IVec3::ZERO, Color::RED.into());
+        model.set(IVec3::new(-1, 15, 0), Color::GREEN.into());
+
+        let mut world = World::new();
+        world.paste(&model, IVec3::new(1, 1, 0));
+
+        assert_eq!(Voxel::from(Color::RED), world.get(IVec3::new(1, 1, 0)));
+        assert_eq!(Voxel::from(Color::GREEN), world.get(IVec3::new(0, 16, 0)));
+        assert_eq!(2, world.chunks.len());
+    }
+
+    #[test]
+    fn set_material_skips_empty_voxels() {
+        let mut world = World::new();
+        world.set(IVec3::ZERO, Color::RED.into());
+        world.set_material(5);
+
+        assert_eq!(5, world.get(IVec3::ZERO).material);
+        assert_eq!(Voxel::EMPTY, world.get(IVec3::X));
+    }
+
+    #[test]
+    fn chunk_bounds() {
+        let mut world = World::new();
+        world.set(IVec3::new(-20, 0, 40), Color::RED.into());
+        world.set(IVec3::new(5, -1, 0), Color::RED.into());
+
+        assert_eq!(
+            Some((IVec3::new(-2, -1, 0), IVec3::new(1, 1, 3))),
+            world.chunk_bounds()
+        );
+    }
+
+    #[test]
+    fn flatten_empty_world() {
+        let flat = World::new().flatten();
+
+        assert_eq!(vec![FlatWorld::NO_CHUNK], flat.chunk_roots);
+        assert_eq!(vec![FlatNode::leaf(Voxel::EMPTY)], flat.nodes);
+        assert!(flat_get(&flat, IVec3::splat(3)).is_empty());
+    }
+
+    #[test]
+    fn flatten_chunk_table() {
+        let mut world = World::new();
+        world.set(IVec3::new(-1, 0, 0), Voxel::new(Color::RED, 1));
+        world.insert_chunk(IVec3::new(1, 0, 1), Chunk::all_same(Color::BLUE.into()));
+        // a chunk with all voxels cleared is left out of the table
+        world.set(IVec3::new(0, 0, 20), Color::GREEN.into());
+        world.clear(IVec3::new(0, 0, 20));
+
+        let flat = world.flatten();
+
+        assert_eq!(IVec3::new(-1, 0, 0), flat.chunk_lo);
+        assert_eq!(UVec3::new(3, 1, 2), flat.chunk_count);
+        assert_eq!(6, flat.chunk_roots.len());
+
+        let root = |chunk_pos: IVec3| {
+            let table_pos = (chunk_pos - flat.chunk_lo).as_uvec3();
+            flat.chunk_roots[FlatWorld::chunk_index(flat.chunk_count, table_pos)]
+        };
+
+        let missing_chunks = [
+            IVec3::new(0, 0, 0),
+            IVec3::new(1, 0, 0),
+            IVec3::new(-1, 0, 1),
+            IVec3::new(0, 0, 1),
+        ];
+
+        for chunk_pos in missing_chunks {
+            assert_eq!(FlatWorld::NO_CHUNK, root(chunk_pos), "chunk {chunk_pos}");
+        }
+
+        // a uniform chunk is a single leaf
+        let uniform_root = root(IVec3::new(1, 0, 1));
+        assert_eq!(
+            FlatNode::leaf(Color::BLUE.into()),
+            flat.nodes[uniform_root as usize]
+        );
+        assert_eq!(
+            FlatNode::BRANCH,
+            flat.nodes[root(IVec3::new(-1, 0, 0)) as usize].kind
+        );
+    }
+
+    #[test]
+    fn flatten_matches_get() {
+        let mut world = World::new_sphere(10.0);
+        world.set(IVec3::new(-10, -10, -10), Voxel::new(Color::RED, 4));
+
+        let flat = world.flatten();
+
+        assert_eq!(IVec3::splat(-1), flat.chunk_lo);
+        assert_eq!(UVec3::splat(2), flat.chunk_count);
+
+        for z in -20..20 {
+            for y in -20..20 {
+                for x in -20..20 {
+                    let pos = IVec3::new(x, y, z);
+                    assert_eq!(world.get(pos), flat_get(&flat, pos), "voxel {pos}");
+                }
+            }
+        }
+    }
+}